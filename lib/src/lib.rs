@@ -13,6 +13,7 @@ use bindings::{libquil_error, libquil_error_t, libquil_error_t_LIBQUIL_ERROR_SUC
 
 pub mod quilc;
 pub mod qvm;
+pub mod verify;
 
 #[allow(dead_code)]
 pub(crate) mod bindings {
@@ -15,6 +15,44 @@ use std::{
     str::FromStr,
 };
 
+mod best_of;
+#[cfg(feature = "cache")]
+pub mod cache;
+mod check;
+mod compiler;
+pub mod directives;
+mod gateset;
+pub mod isa;
+mod json;
+pub(crate) mod metadata;
+mod optimize;
+pub mod parametric;
+pub(crate) mod placement;
+pub mod qcs;
+pub mod render;
+mod report;
+mod rewiring;
+mod schedule;
+mod summary;
+mod synthesis;
+pub mod unitary;
+pub mod validation;
+
+pub use best_of::{compile_best_of, Attempt, BestOf, Objective, ScoreFn};
+pub use check::{check_native, check_protoquil, Violation, ViolationKind};
+pub use compiler::{compile_batch, CompileOptions, Compiler, RewiringStrategy};
+pub use gateset::{translate_to_gateset, GateSet};
+pub use optimize::{optimize, GateStats, Optimized};
+pub use parametric::{ParameterValues, ParametricProgram};
+pub use placement::Placement;
+pub use report::{compile_with_report, CompilationReport};
+pub use rewiring::{LogicalMeasurements, Rewiring};
+pub use schedule::{schedule, Schedule, TimeWindow};
+pub use summary::{Declaration, ProgramSummary, Sharing};
+pub use synthesis::{synthesize_unitary, Synthesis};
+#[cfg(feature = "ndarray")]
+pub use unitary::{program_unitary, program_unitary_with_limit};
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("error when calling quilc_compile_quil: {0}")]
@@ -47,19 +85,76 @@ pub enum Error {
     ProgramMemoryType(String),
     #[error("unknown memory type: {0}")]
     UnknownMemoryType(u32),
+    #[error("failed to convert QCS ISA: {0}")]
+    QcsIsa(#[from] qcs::Error),
+    #[error("chip was not built from an ISA document")]
+    MissingIsa,
+    #[error("qubit {0} is not on the chip")]
+    UnknownQubit(u32),
+    #[error("edge {0}-{1} is not on the chip")]
+    UnknownEdge(u32, u32),
+    #[error("failed to parse program text: {0}")]
+    ParseProgramText(String),
+    #[error("failed to render chip: {0}")]
+    RenderChip(String),
+    #[error("compile option is not supported by libquil: {0}")]
+    UnsupportedOption(&'static str),
+    #[error("program uses {required} qubits but the chip has only {available} live qubits")]
+    InsufficientQubits { required: usize, available: usize },
+    #[error("failed to print program: {0}")]
+    ToQuil(String),
+    #[error("compilation cache I/O failed: {0}")]
+    CacheIo(#[from] std::io::Error),
+    #[error("failed to serialize compilation cache entry: {0}")]
+    CacheEntry(serde_json::Error),
+    #[error("none of {attempts} compilation attempts could be scored ({failures} failed)")]
+    NoScoredAttempt { attempts: usize, failures: usize },
+    #[error("invalid best-of search: {0}")]
+    InvalidSearch(String),
+    #[error("invalid placement: {0}")]
+    InvalidPlacement(String),
+    #[error("invalid rewiring: {0}")]
+    InvalidRewiring(String),
+    #[error("cannot schedule instruction {index}: {reason}")]
+    Unschedulable { index: usize, reason: String },
+    #[error(
+        "logical qubit {logical} was placed on physical qubit {expected} but ended on {actual:?}"
+    )]
+    PlacementMismatch {
+        logical: u32,
+        expected: u32,
+        actual: Option<u32>,
+    },
+    #[error("compilation result has no metadata")]
+    MissingMetadata,
+    #[error("invalid chip ISA: {0}")]
+    InvalidIsa(#[from] validation::IsaErrors),
+    #[error("cannot express these gates in the target gate set: {}", .0.join(", "))]
+    Untranslatable(Vec<String>),
+    #[error(transparent)]
+    Unitary(#[from] unitary::Error),
+    #[error("invalid block directives: {0}")]
+    Directive(#[from] directives::Error),
+    #[error("invalid parameter values: {0}")]
+    Parameter(#[from] parametric::Error),
 }
 /// A quilc chip specification
 #[derive(Clone, Debug)]
-pub struct Chip(chip_specification);
+pub struct Chip {
+    ptr: chip_specification,
+    /// The document the chip was parsed from, when it was built from an ISA document
+    device: Option<isa::TargetDevice>,
+}
 
 // The Chip memory held by libquil is never mutated and
 // is thus `Send`.
 unsafe impl Send for Chip {}
 
-impl TryFrom<CString> for Chip {
-    type Error = Error;
-
-    fn try_from(json: CString) -> Result<Self, Self::Error> {
+impl Chip {
+    fn parse(json: CString, device: Option<isa::TargetDevice>) -> Result<Self, Error> {
+        if let Ok(json) = json.to_str() {
+            validation::validate_isa_json(json)?;
+        }
         crate::init_libquil()?;
 
         let ptr = json.into_raw();
@@ -71,7 +166,19 @@ impl TryFrom<CString> for Chip {
             let _ = CString::from_raw(ptr);
         }
 
-        Ok(Chip(chip))
+        Ok(Chip { ptr: chip, device })
+    }
+}
+
+impl TryFrom<CString> for Chip {
+    type Error = Error;
+
+    fn try_from(json: CString) -> Result<Self, Self::Error> {
+        let device = json
+            .to_str()
+            .ok()
+            .and_then(|s| s.parse::<isa::TargetDevice>().ok());
+        Chip::parse(json, device)
     }
 }
 
@@ -83,10 +190,75 @@ impl FromStr for Chip {
     }
 }
 
+impl TryFrom<&isa::TargetDevice> for Chip {
+    type Error = Error;
+
+    fn try_from(device: &isa::TargetDevice) -> Result<Self, Self::Error> {
+        Chip::parse(CString::new(device.to_json())?, Some(device.clone()))
+    }
+}
+
+impl TryFrom<&isa::Isa> for Chip {
+    type Error = Error;
+
+    fn try_from(isa: &isa::Isa) -> Result<Self, Self::Error> {
+        (&isa::TargetDevice::from(isa.clone())).try_into()
+    }
+}
+
+impl TryFrom<&qcs::InstructionSetArchitecture> for Chip {
+    type Error = Error;
+
+    fn try_from(qcs: &qcs::InstructionSetArchitecture) -> Result<Self, Self::Error> {
+        (&isa::Isa::try_from(qcs)?).try_into()
+    }
+}
+
+impl Chip {
+    /// The ISA this chip was built from, if it was built from an ISA document
+    pub fn isa(&self) -> Option<&isa::Isa> {
+        self.device.as_ref().map(|device| &device.isa)
+    }
+
+    /// The whole document this chip was built from, including its specs, if it was built
+    /// from an ISA document
+    pub fn target_device(&self) -> Option<&isa::TargetDevice> {
+        self.device.as_ref()
+    }
+
+    fn require_isa(&self) -> Result<&isa::Isa, Error> {
+        self.isa().ok_or(Error::MissingIsa)
+    }
+
+    fn require_device(&self) -> Result<&isa::TargetDevice, Error> {
+        self.device.as_ref().ok_or(Error::MissingIsa)
+    }
+
+    /// Build a new [`Chip`] on which only the given qubits are usable. Every other
+    /// qubit, and every edge touching one, is marked dead so quilc never addresses it.
+    /// The specs of the qubits and edges that remain are carried over.
+    pub fn restrict_to(&self, qubits: &[u32]) -> Result<Chip, Error> {
+        (&self.require_device()?.restrict_to(qubits)?).try_into()
+    }
+
+    /// Build a new [`Chip`] with the given edges marked dead, keeping the specs of
+    /// everything else
+    pub fn without_edges(&self, edges: &[(u32, u32)]) -> Result<Chip, Error> {
+        (&self.require_device()?.without_edges(edges)?).try_into()
+    }
+
+    /// Build a [`Chip`] from a QCS API `InstructionSetArchitecture` JSON document,
+    /// such as one exported for a Rigetti device
+    pub fn from_qcs_isa_json(json: &str) -> Result<Self, Error> {
+        let qcs: qcs::InstructionSetArchitecture = json.parse()?;
+        (&qcs).try_into()
+    }
+}
+
 impl Drop for Chip {
     fn drop(&mut self) {
         unsafe {
-            bindings::lisp_release_handle.unwrap()(self.0 as *mut _);
+            bindings::lisp_release_handle.unwrap()(self.ptr as *mut _);
         }
     }
 }
@@ -147,9 +319,23 @@ impl Program {
             Ok(program_string)
         }
     }
+
+    /// Parse the program text with `quil-rs`, for analysis on the Rust side
+    pub(crate) fn to_quil_rs(&self) -> Result<quil_rs::Program, Error> {
+        self.to_string()?
+            .parse()
+            .map_err(|e: quil_rs::program::ProgramError| Error::ParseProgramText(e.to_string()))
+    }
+
+    /// Parse a program built with `quil-rs`
+    pub(crate) fn from_quil_rs(program: &quil_rs::Program) -> Result<Self, Error> {
+        let text =
+            quil_rs::quil::Quil::to_quil(program).map_err(|e| Error::ToQuil(e.to_string()))?;
+        text.parse()
+    }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum MemoryType {
     Bit,
     Octet,
@@ -188,22 +374,49 @@ pub fn program_memory_type(program: &Program, region: &str) -> Result<MemoryType
 }
 
 /// Compiles the [`Program`] for the given [`Chip`]
+///
+/// libquil only reports metadata for protoquil compiles, so the metadata here is derived
+/// from the compiled program and the chip's ISA; see [`CompilationMetadata`] for which
+/// fields may be `None`. The metadata is `None` if the compiled program cannot be parsed.
 pub fn compile_program(program: &Program, chip: &Chip) -> Result<CompilationResult, Error> {
+    compile_quil(program, chip, true)
+}
+
+/// Compiles the [`Program`] for the given [`Chip`], deriving metadata only if
+/// `with_metadata` is set
+pub(crate) fn compile_quil(
+    program: &Program,
+    chip: &Chip,
+    with_metadata: bool,
+) -> Result<CompilationResult, Error> {
     init_libquil()?;
     let mut compiled_program: quil_program = std::ptr::null_mut();
 
     unsafe {
-        let err = quilc_compile_quil.unwrap()(program.0, chip.0, &mut compiled_program);
+        let err = quilc_compile_quil.unwrap()(program.0, chip.ptr, &mut compiled_program);
         crate::handle_libquil_error(err).map_err(Error::CompileQuil)?;
     }
 
-    Ok(CompilationResult {
-        program: Program(compiled_program),
-        metadata: None,
-    })
+    let program = Program(compiled_program);
+    let metadata = if with_metadata {
+        program
+            .to_quil_rs()
+            .ok()
+            .map(|parsed| metadata::estimate(&parsed, chip.isa()))
+    } else {
+        None
+    };
+    Ok(CompilationResult { program, metadata })
 }
 
+/// Statistics about a compiled program
+///
+/// For non-protoquil compiles, `topological_swaps` and `qpu_runtime_estimation` are
+/// always `None`; depths, gate volume and fidelity are `None` for programs with control
+/// flow; and duration and fidelity are `None` unless the chip's ISA describes every
+/// operation used.
 #[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CompilationMetadata {
     pub final_rewiring: Vec<u32>,
     pub gate_depth: Option<u32>,
@@ -279,6 +492,42 @@ pub struct CompilationResult {
     pub metadata: Option<CompilationMetadata>,
 }
 
+/// The stable serialized form of a [`CompilationResult`], with the program as Quil text
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SerializedCompilationResult {
+    program: String,
+    metadata: Option<CompilationMetadata>,
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for CompilationResult {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SerializedCompilationResult {
+            program: self
+                .program
+                .to_string()
+                .map_err(serde::ser::Error::custom)?,
+            metadata: self.metadata.clone(),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for CompilationResult {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let serialized = SerializedCompilationResult::deserialize(deserializer)?;
+        Ok(CompilationResult {
+            program: serialized
+                .program
+                .parse()
+                .map_err(serde::de::Error::custom)?,
+            metadata: serialized.metadata,
+        })
+    }
+}
+
 /// Compiles the [`Program`] for the given [`Chip`] and restricts
 /// the resulting [`Program`] to satisfy "protoquil" constraints
 pub fn compile_protoquil(program: &Program, chip: &Chip) -> Result<CompilationResult, Error> {
@@ -290,7 +539,7 @@ pub fn compile_protoquil(program: &Program, chip: &Chip) -> Result<CompilationRe
     unsafe {
         let err = quilc_compile_protoquil.unwrap()(
             program.0,
-            chip.0,
+            chip.ptr,
             std::ptr::addr_of!(metadata_ptr) as *mut _,
             &mut compiled_program,
         );
@@ -319,7 +568,10 @@ pub fn get_chip() -> Result<Chip, Error> {
         crate::handle_libquil_error(err).map_err(Error::BuildNqLinearChip)?;
     }
 
-    Ok(Chip(chip))
+    Ok(Chip {
+        ptr: chip,
+        device: None,
+    })
 }
 
 /// Prints the given [`Program`] to stdout
@@ -433,6 +685,7 @@ pub fn generate_rb_sequence(
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VersionInfo {
     pub version: String,
     pub githash: String,
@@ -473,10 +726,39 @@ pub fn get_version_info() -> Result<VersionInfo, Error> {
     }
 }
 
+/// Test fixtures shared by the modules of `quilc`
 #[cfg(test)]
-mod tests {
+pub(crate) mod fixtures {
     use std::{fs::File, io::Read};
 
+    use super::{isa::TargetDevice, Chip};
+
+    pub(crate) fn read_data_file(name: &str) -> String {
+        let mut file = File::open(format!(
+            "{}/data/{}",
+            std::env::var("CARGO_MANIFEST_DIR").unwrap(),
+            name
+        ))
+        .unwrap();
+        let mut file_str = String::new();
+        file.read_to_string(&mut file_str).unwrap();
+        file_str
+    }
+
+    /// The Aspen-9 ISA document in `data/aspen-9-isa.json`
+    pub(crate) fn aspen_9_device() -> TargetDevice {
+        read_data_file("aspen-9-isa.json").parse().unwrap()
+    }
+
+    /// A chip parsed from the Aspen-9 ISA document
+    pub(crate) fn aspen_9() -> Chip {
+        read_data_file("aspen-9-isa.json").parse().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fixtures::read_data_file;
     use super::*;
     use assert2::let_assert;
 
@@ -515,6 +797,14 @@ MEASURE 1 ro[1]
         assert!(error.contains("unexpected token of type :INDENT"));
     }
 
+    #[test]
+    fn test_chip_validation_error() {
+        let json = r#"{"isa": {"1Q": {"0": {"id": 0}}, "2Q": {"0-1": {"ids": [0, 1]}}}}"#;
+        let_assert!(Error::InvalidIsa(errors) = Chip::from_str(json).err().unwrap());
+        assert_eq!(errors.0[0].pointer, "/isa/2Q/0-1");
+        assert_eq!(errors.0[0].reason, validation::Reason::DanglingQubit(1));
+    }
+
     #[test]
     fn test_program_compilation_error() {
         // Program should parse correctly, but compilation should fail
@@ -535,18 +825,6 @@ MEASURE 1 ro[1]
         assert!(error.contains("Unrecognized instruction"));
     }
 
-    fn read_data_file(name: &str) -> String {
-        let mut file = File::open(format!(
-            "{}/data/{}",
-            std::env::var("CARGO_MANIFEST_DIR").unwrap(),
-            name
-        ))
-        .unwrap();
-        let mut file_str = String::new();
-        file.read_to_string(&mut file_str).unwrap();
-        file_str
-    }
-
     #[test]
     fn test_compile_protoquil() {
         let program = new_quil_program();
@@ -554,6 +832,70 @@ MEASURE 1 ro[1]
         compile_program(&program, &chip).unwrap();
     }
 
+    #[test]
+    fn test_compile_program_metadata() {
+        let program = Program::from_str("H 0; CNOT 0 1").unwrap();
+        let chip = Chip::from_str(&read_data_file("aspen-9-isa.json")).unwrap();
+        let metadata = compile_program(&program, &chip).unwrap().metadata.unwrap();
+        assert!(!metadata.final_rewiring.is_empty());
+        assert!(metadata.multiqubit_gate_depth.unwrap() >= 1);
+        assert!(metadata.gate_depth.unwrap() >= metadata.multiqubit_gate_depth.unwrap());
+        assert!(metadata.program_duration.is_some());
+        assert!(metadata.program_fidelity.is_some());
+        assert!(metadata.topological_swaps.is_none());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_compilation_result_serde_round_trip() {
+        let program = Program::from_str("H 0; CNOT 0 1").unwrap();
+        let chip = Chip::from_str(&read_data_file("aspen-9-isa.json")).unwrap();
+        let result = compile_protoquil(&program, &chip).unwrap();
+
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["program"], result.program.to_string().unwrap());
+        assert_eq!(
+            json["metadata"]["final_rewiring"],
+            serde_json::to_value(&result.metadata.as_ref().unwrap().final_rewiring).unwrap()
+        );
+
+        let reparsed: CompilationResult = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            reparsed.program.to_string().unwrap(),
+            result.program.to_string().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_compile_with_qcs_isa_chip() {
+        let program = new_quil_program();
+        let chip = Chip::from_qcs_isa_json(&read_data_file("qcs-isa.json")).unwrap();
+        compile_program(&program, &chip).unwrap();
+    }
+
+    #[test]
+    fn test_restricted_chip_placement() {
+        let program = Program::from_str("H 0; CNOT 0 1").unwrap();
+        let chip = Chip::from_str(&read_data_file("aspen-9-isa.json"))
+            .unwrap()
+            .restrict_to(&[10, 11])
+            .unwrap();
+        let compiled: quil_rs::Program = compile_program(&program, &chip)
+            .unwrap()
+            .program
+            .to_string()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let expected = [
+            quil_rs::instruction::Qubit::Fixed(10),
+            quil_rs::instruction::Qubit::Fixed(11),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(compiled.get_used_qubits(), &expected);
+    }
+
     #[test]
     fn test_program_string() {
         let expected: quil_rs::Program = sample_quil.parse().unwrap();
@@ -0,0 +1,323 @@
+//! Compiling a program several times with different seeds and keeping the best result
+//! according to an objective over its [`CompilationMetadata`]
+
+use super::{
+    Chip, CompilationMetadata, CompilationResult, CompileOptions, Compiler, Error, Placement,
+    Program, RewiringStrategy,
+};
+
+/// A scoring function for [`Objective::Custom`]
+pub type ScoreFn = Box<dyn Fn(&CompilationMetadata) -> Option<f64> + Send + Sync>;
+
+/// What makes one compilation better than another. Every objective maps metadata to a
+/// score where higher is better; attempts whose metadata lacks the fields an objective
+/// needs have no score and cannot win.
+pub enum Objective {
+    /// Maximize `program_fidelity`
+    MaxProgramFidelity,
+    /// Minimize `multiqubit_gate_depth`
+    MinMultiqubitGateDepth,
+    /// Minimize `topological_swaps`, which libquil only reports for protoquil compiles
+    MinTopologicalSwaps,
+    /// Maximize `fidelity * program_fidelity - multiqubit_gate_depth * depth - topological_swaps * swaps`.
+    /// A field with a zero weight is not required to be present.
+    Weighted {
+        fidelity: f64,
+        multiqubit_gate_depth: f64,
+        topological_swaps: f64,
+    },
+    /// Any scoring function
+    Custom(ScoreFn),
+}
+
+impl std::fmt::Debug for Objective {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Objective::MaxProgramFidelity => write!(f, "MaxProgramFidelity"),
+            Objective::MinMultiqubitGateDepth => write!(f, "MinMultiqubitGateDepth"),
+            Objective::MinTopologicalSwaps => write!(f, "MinTopologicalSwaps"),
+            Objective::Weighted {
+                fidelity,
+                multiqubit_gate_depth,
+                topological_swaps,
+            } => f
+                .debug_struct("Weighted")
+                .field("fidelity", fidelity)
+                .field("multiqubit_gate_depth", multiqubit_gate_depth)
+                .field("topological_swaps", topological_swaps)
+                .finish(),
+            Objective::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
+
+impl Objective {
+    /// The score of a compilation, higher being better
+    pub fn score(&self, metadata: &CompilationMetadata) -> Option<f64> {
+        match self {
+            Objective::MaxProgramFidelity => metadata.program_fidelity,
+            Objective::MinMultiqubitGateDepth => metadata
+                .multiqubit_gate_depth
+                .map(|depth| -f64::from(depth)),
+            Objective::MinTopologicalSwaps => {
+                metadata.topological_swaps.map(|swaps| -f64::from(swaps))
+            }
+            Objective::Weighted {
+                fidelity,
+                multiqubit_gate_depth,
+                topological_swaps,
+            } => {
+                let term = |weight: f64, value: Option<f64>| {
+                    if weight == 0.0 {
+                        Some(0.0)
+                    } else {
+                        value.map(|value| weight * value)
+                    }
+                };
+                Some(
+                    term(*fidelity, metadata.program_fidelity)?
+                        - term(
+                            *multiqubit_gate_depth,
+                            metadata.multiqubit_gate_depth.map(f64::from),
+                        )?
+                        - term(
+                            *topological_swaps,
+                            metadata.topological_swaps.map(f64::from),
+                        )?,
+                )
+            }
+            Objective::Custom(score) => score(metadata),
+        }
+    }
+
+    /// Whether the objective cannot score a compilation without `topological_swaps`
+    fn requires_topological_swaps(&self) -> bool {
+        match self {
+            Objective::MinTopologicalSwaps => true,
+            Objective::Weighted {
+                topological_swaps, ..
+            } => *topological_swaps != 0.0,
+            _ => false,
+        }
+    }
+}
+
+/// Check that a search with `options` can tell its attempts apart and score them
+fn check_search(options: &CompileOptions, objective: &Objective) -> Result<(), Error> {
+    match &options.initial_rewiring {
+        None | Some(Placement::Strategy(RewiringStrategy::Random)) => {}
+        Some(placement) => {
+            return Err(Error::InvalidSearch(format!(
+                "every attempt would compile from the same placement ({placement:?}); use the random strategy"
+            )))
+        }
+    }
+    if objective.requires_topological_swaps() && !options.protoquil {
+        return Err(Error::InvalidSearch(
+            "topological_swaps is only reported for protoquil compiles".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// One of the compilations made by [`compile_best_of`]
+#[derive(Debug)]
+pub struct Attempt {
+    pub seed: u64,
+    /// The metadata of the compiled program, or the error compiling it
+    pub outcome: Result<CompilationMetadata, Error>,
+    pub score: Option<f64>,
+}
+
+/// The winning compilation and a record of every attempt
+#[derive(Debug)]
+pub struct BestOf {
+    pub best: CompilationResult,
+    /// The index of `best` in `attempts`
+    pub best_index: usize,
+    pub attempts: Vec<Attempt>,
+}
+
+impl BestOf {
+    /// The scores of the attempts that have one
+    pub fn scores(&self) -> Vec<f64> {
+        self.attempts.iter().filter_map(|a| a.score).collect()
+    }
+
+    /// The mean score over the attempts that have one
+    pub fn mean_score(&self) -> Option<f64> {
+        let scores = self.scores();
+        (!scores.is_empty()).then(|| scores.iter().sum::<f64>() / scores.len() as f64)
+    }
+
+    /// The number of attempts that failed to compile
+    pub fn failures(&self) -> usize {
+        self.attempts.iter().filter(|a| a.outcome.is_err()).count()
+    }
+}
+
+impl Compiler<'_> {
+    /// Compile the program `n` times with seeds counting up from [`CompileOptions::seed`]
+    /// (or 0), and keep the result that scores best on `objective`. Attempts use
+    /// [`RewiringStrategy::Random`] so that each seed starts from a different placement.
+    /// Metadata is always collected.
+    ///
+    /// Fails with [`Error::InvalidSearch`] if the options choose any other placement,
+    /// since every attempt would then be the same, or if the objective needs
+    /// `topological_swaps` and the options do not compile to protoquil.
+    pub fn compile_best_of(
+        &self,
+        program: &Program,
+        n: usize,
+        objective: &Objective,
+    ) -> Result<BestOf, Error> {
+        check_search(self.options(), objective)?;
+        let base_seed = self.options().seed.unwrap_or(0);
+        let compiler = self.with_replaced_options(CompileOptions {
+            initial_rewiring: Some(RewiringStrategy::Random.into()),
+            return_metadata: true,
+            ..self.options().clone()
+        });
+
+        let seeds = (0..n as u64)
+            .map(|i| base_seed.wrapping_add(i))
+            .collect::<Vec<_>>();
+        let results = seeds
+            .iter()
+            .map(|&seed| compiler.compile_with_seed(program, seed))
+            .collect::<Vec<_>>();
+
+        let mut best: Option<(usize, f64, CompilationResult)> = None;
+        let mut attempts = Vec::with_capacity(n);
+        for (index, (seed, result)) in seeds.into_iter().zip(results).enumerate() {
+            let (outcome, score) = match result {
+                Ok(result) => {
+                    let metadata = result.metadata.clone().unwrap_or_default();
+                    let score = objective.score(&metadata);
+                    if let Some(score) = score {
+                        if best.as_ref().is_none_or(|(_, best, _)| score > *best) {
+                            best = Some((index, score, result));
+                        }
+                    }
+                    (Ok(metadata), score)
+                }
+                Err(error) => (Err(error), None),
+            };
+            attempts.push(Attempt {
+                seed,
+                outcome,
+                score,
+            });
+        }
+
+        match best {
+            Some((best_index, _, best)) => Ok(BestOf {
+                best,
+                best_index,
+                attempts,
+            }),
+            None => Err(Error::NoScoredAttempt {
+                attempts: attempts.len(),
+                failures: attempts.iter().filter(|a| a.outcome.is_err()).count(),
+            }),
+        }
+    }
+}
+
+/// Compile the program `n` times for the chip and keep the best result on `objective`;
+/// see [`Compiler::compile_best_of`]
+pub fn compile_best_of(
+    program: &Program,
+    chip: &Chip,
+    n: usize,
+    objective: &Objective,
+) -> Result<BestOf, Error> {
+    Compiler::new(chip).compile_best_of(program, n, objective)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quilc::fixtures::aspen_9;
+
+    fn metadata(fidelity: f64, depth: u32, swaps: Option<u32>) -> CompilationMetadata {
+        CompilationMetadata {
+            multiqubit_gate_depth: Some(depth),
+            topological_swaps: swaps,
+            program_fidelity: Some(fidelity),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_objective_scores() {
+        let m = metadata(0.9, 4, None);
+        assert_eq!(Objective::MaxProgramFidelity.score(&m), Some(0.9));
+        assert_eq!(Objective::MinMultiqubitGateDepth.score(&m), Some(-4.0));
+        assert_eq!(Objective::MinTopologicalSwaps.score(&m), None);
+
+        let weighted = Objective::Weighted {
+            fidelity: 10.0,
+            multiqubit_gate_depth: 0.5,
+            topological_swaps: 0.0,
+        };
+        assert_eq!(weighted.score(&m), Some(7.0));
+        let weighted = Objective::Weighted {
+            fidelity: 1.0,
+            multiqubit_gate_depth: 0.0,
+            topological_swaps: 1.0,
+        };
+        assert_eq!(weighted.score(&m), None);
+
+        let custom = Objective::Custom(Box::new(|m| m.gate_depth.map(f64::from)));
+        assert_eq!(custom.score(&m), None);
+    }
+
+    #[test]
+    fn test_check_search() {
+        let options = |initial_rewiring, protoquil| CompileOptions {
+            initial_rewiring,
+            protoquil,
+            ..Default::default()
+        };
+        let random = Some(RewiringStrategy::Random.into());
+        let naive = Some(RewiringStrategy::Naive.into());
+        let explicit = Some(Placement::Explicit([(0, 1)].into()));
+
+        assert!(check_search(&options(None, false), &Objective::MaxProgramFidelity).is_ok());
+        assert!(check_search(&options(random, true), &Objective::MinTopologicalSwaps).is_ok());
+        for placement in [naive, explicit] {
+            assert!(matches!(
+                check_search(&options(placement, false), &Objective::MaxProgramFidelity),
+                Err(Error::InvalidSearch(_))
+            ));
+        }
+        assert!(matches!(
+            check_search(&options(None, false), &Objective::MinTopologicalSwaps),
+            Err(Error::InvalidSearch(_))
+        ));
+    }
+
+    #[test]
+    fn test_compile_best_of() {
+        let chip = aspen_9();
+        let program: Program = "CNOT 0 1\nCNOT 0 2\nCNOT 1 2".parse().unwrap();
+
+        let best_of = compile_best_of(&program, &chip, 4, &Objective::MaxProgramFidelity).unwrap();
+        assert_eq!(best_of.attempts.len(), 4);
+        assert_eq!(best_of.failures(), 0);
+        let best = best_of
+            .best
+            .metadata
+            .as_ref()
+            .unwrap()
+            .program_fidelity
+            .unwrap();
+        assert!(best_of.scores().iter().all(|&score| score <= best));
+        assert_eq!(best_of.attempts[best_of.best_index].score, Some(best));
+        assert_eq!(
+            best_of.attempts.iter().map(|a| a.seed).collect::<Vec<_>>(),
+            vec![0, 1, 2, 3]
+        );
+    }
+}
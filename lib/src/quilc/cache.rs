@@ -0,0 +1,325 @@
+//! A persistent, content-addressed store of [`CompilationResult`]s, so that a program
+//! compiled against the same chip with the same options is only compiled once.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{ErrorKind, Write as _},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::SystemTime,
+};
+
+use sha2::{Digest, Sha256};
+
+use super::{
+    get_version_info, Chip, CompilationResult, CompileOptions, Error, Placement, Program,
+    RewiringStrategy,
+};
+
+const ENTRY_EXTENSION: &str = "json";
+
+/// The options as they enter a cache key. Every field that can change the compiled
+/// result is written out by name, so the key does not depend on the options' `Debug`
+/// output.
+fn options_key(options: &CompileOptions) -> String {
+    let placement = match &options.initial_rewiring {
+        None => "none".to_string(),
+        Some(Placement::Strategy(strategy)) => strategy.to_string(),
+        Some(Placement::Explicit(placement)) => {
+            let mut text = "explicit".to_string();
+            for (logical, physical) in placement {
+                let _ = write!(text, " {logical}:{physical}");
+            }
+            text
+        }
+    };
+    let seed = options
+        .seed
+        .map_or_else(|| "none".to_string(), |s| s.to_string());
+    format!(
+        "protoquil={}\ninitial_rewiring={placement}\nreturn_metadata={}\nseed={seed}",
+        options.protoquil, options.return_metadata,
+    )
+}
+
+/// Counts of cache lookups since the cache was opened
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Compilation results stored as JSON files in a directory, one per cache key.
+/// When the directory grows beyond `max_bytes`, the least recently used entries are removed.
+#[derive(Debug)]
+pub struct CompilationCache {
+    directory: PathBuf,
+    max_bytes: u64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl CompilationCache {
+    /// Open (creating if necessary) a cache in `directory` holding at most `max_bytes`
+    pub fn open(directory: impl Into<PathBuf>, max_bytes: u64) -> Result<Self, Error> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+        Ok(Self {
+            directory,
+            max_bytes,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The cache key for compiling `program` for `chip` with `options`: a hex SHA-256
+    /// of the program text as printed by quilc, the chip's ISA JSON including its specs,
+    /// the options and the quilc version. Returns `None` when the compilation can't be cached, either
+    /// because the chip has no ISA or because the options make it nondeterministic.
+    pub fn key(
+        program: &Program,
+        chip: &Chip,
+        options: &CompileOptions,
+    ) -> Result<Option<String>, Error> {
+        let Some(device) = chip.target_device() else {
+            return Ok(None);
+        };
+        if options.initial_rewiring == Some(RewiringStrategy::Random.into())
+            && options.seed.is_none()
+        {
+            return Ok(None);
+        }
+
+        let parts = [
+            program.to_string()?,
+            device.to_json(),
+            options_key(options),
+            get_version_info()?.to_string(),
+        ];
+        let mut hasher = Sha256::new();
+        for part in &parts {
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part.as_bytes());
+        }
+        let key = hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        Ok(Some(key))
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.directory.join(key).with_extension(ENTRY_EXTENSION)
+    }
+
+    /// Look up an entry, counting the hit or miss. Unreadable entries are removed and
+    /// treated as misses, as are entries that can't be marked as recently used, such as
+    /// those in a read-only directory.
+    pub fn get(&self, key: &str) -> Result<Option<CompilationResult>, Error> {
+        let path = self.path(key);
+        let result = match fs::read_to_string(&path) {
+            Ok(json) => match serde_json::from_str::<CompilationResult>(&json) {
+                // Mark the entry as recently used for eviction
+                Ok(result) => match fs::File::options()
+                    .append(true)
+                    .open(&path)
+                    .and_then(|file| file.set_modified(SystemTime::now()))
+                {
+                    Ok(()) => Some(result),
+                    Err(error) => {
+                        log::warn!("failed to mark cache entry {key} as used: {error}");
+                        None
+                    }
+                },
+                Err(_) => {
+                    if let Err(error) = fs::remove_file(&path) {
+                        log::warn!("failed to remove corrupt cache entry {key}: {error}");
+                    }
+                    None
+                }
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+
+        let counter = if result.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        Ok(result)
+    }
+
+    /// Store an entry, then evict least recently used entries until the cache fits
+    pub fn insert(&self, key: &str, result: &CompilationResult) -> Result<(), Error> {
+        let json = serde_json::to_string(result).map_err(Error::CacheEntry)?;
+        // Write then rename so that concurrent readers never see a partial entry. Each
+        // writer stages in its own file, without the entry extension so eviction skips it.
+        let mut staging = tempfile::NamedTempFile::new_in(&self.directory)?;
+        staging.write_all(json.as_bytes())?;
+        staging
+            .persist(self.path(key))
+            .map_err(|error| Error::CacheIo(error.error))?;
+        self.evict()
+    }
+
+    fn evict(&self) -> Result<(), Error> {
+        let mut entries = vec![];
+        for entry in fs::read_dir(&self.directory)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(ENTRY_EXTENSION) {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            entries.push((metadata.modified()?, metadata.len(), path));
+        }
+
+        let mut total = entries.iter().map(|(_, len, _)| len).sum::<u64>();
+        entries.sort();
+        for (_, len, path) in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            match fs::remove_file(&path) {
+                Ok(()) => total -= len,
+                // Another process evicted it first
+                Err(e) if e.kind() == ErrorKind::NotFound => total -= len,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove every entry
+    pub fn clear(&self) -> Result<(), Error> {
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) == Some(ENTRY_EXTENSION) {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quilc::fixtures::aspen_9;
+    use crate::quilc::Compiler;
+
+    #[test]
+    fn test_compiler_uses_cache() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = CompilationCache::open(directory.path(), 1 << 20).unwrap();
+        let chip = aspen_9();
+        let compiler = Compiler::new(&chip).with_cache(&cache);
+        let program: Program = "H 0\nCNOT 0 1".parse().unwrap();
+
+        let first = compiler.compile(&program).unwrap();
+        assert_eq!(cache.stats(), CacheStats { hits: 0, misses: 1 });
+        let second = compiler.compile(&program).unwrap();
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 1 });
+        assert_eq!(
+            first.program.to_string().unwrap(),
+            second.program.to_string().unwrap()
+        );
+
+        // Different options are a different entry
+        let options = CompileOptions {
+            protoquil: true,
+            ..Default::default()
+        };
+        Compiler::new(&chip)
+            .with_options(options)
+            .with_cache(&cache)
+            .compile(&program)
+            .unwrap();
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 2 });
+    }
+
+    #[test]
+    fn test_eviction() {
+        let directory = tempfile::tempdir().unwrap();
+        let chip = aspen_9();
+        let program: Program = "H 0\nCNOT 0 1".parse().unwrap();
+        let result = Compiler::new(&chip).compile(&program).unwrap();
+        let size = serde_json::to_string(&result).unwrap().len() as u64;
+
+        let cache = CompilationCache::open(directory.path(), 2 * size).unwrap();
+        for key in ["a", "b", "c"] {
+            cache.insert(key, &result).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(cache.get("a").unwrap().is_none());
+        assert!(cache.get("b").unwrap().is_some());
+        assert!(cache.get("c").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_corrupt_entry_is_a_miss() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = CompilationCache::open(directory.path(), 1 << 20).unwrap();
+        fs::write(cache.path("bad"), "{").unwrap();
+
+        assert!(cache.get("bad").unwrap().is_none());
+        assert!(!cache.path("bad").exists());
+        assert_eq!(cache.stats(), CacheStats { hits: 0, misses: 1 });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_read_only_directory_is_a_miss() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = tempfile::tempdir().unwrap();
+        let cache = CompilationCache::open(directory.path(), 1 << 20).unwrap();
+        fs::write(cache.path("bad"), "{").unwrap();
+        let chip = aspen_9();
+        let result = Compiler::new(&chip)
+            .compile(&"X 0".parse().unwrap())
+            .unwrap();
+        cache.insert("good", &result).unwrap();
+        fs::set_permissions(cache.path("good"), fs::Permissions::from_mode(0o444)).unwrap();
+        fs::set_permissions(directory.path(), fs::Permissions::from_mode(0o555)).unwrap();
+
+        // Neither the corrupt entry's removal nor the other entry's update can be written
+        let bad = cache.get("bad");
+        let good = cache.get("good");
+        fs::set_permissions(directory.path(), fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(bad.unwrap().is_none());
+        assert!(good.is_ok());
+    }
+
+    #[test]
+    fn test_options_key() {
+        let explicit = |physical| CompileOptions {
+            initial_rewiring: Some(Placement::Explicit([(0, physical)].into())),
+            ..Default::default()
+        };
+        assert_eq!(options_key(&explicit(3)), options_key(&explicit(3)));
+        assert_ne!(options_key(&explicit(3)), options_key(&explicit(4)));
+        assert_ne!(
+            options_key(&CompileOptions::default()),
+            options_key(&CompileOptions {
+                return_metadata: false,
+                ..Default::default()
+            })
+        );
+    }
+}
@@ -0,0 +1,249 @@
+//! Checking whether a program is already native to a chip, or already protoquil
+
+use std::{collections::HashSet, fmt::Display};
+
+use quil_rs::instruction::{Gate, Instruction, Qubit};
+
+use super::{
+    isa::{real_parameters, Isa},
+    placement::{fixed_index, fixed_indices},
+    Chip, Error, Program,
+};
+
+/// An instruction that breaks a rule, identified by its index among the program's
+/// body instructions
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Violation {
+    pub index: usize,
+    pub kind: ViolationKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ViolationKind {
+    /// A qubit that is a placeholder or variable rather than a fixed index
+    UnresolvedQubit,
+    /// A qubit the chip does not have
+    UnknownQubit(u32),
+    /// A qubit the chip marks dead
+    DeadQubit(u32),
+    /// A pair of qubits without a live edge between them
+    UnsupportedEdge(u32, u32),
+    /// A gate, with its parameters and qubits, that the chip does not support natively
+    NonNativeGate(String),
+    /// A measurement the chip does not support natively
+    NonNativeMeasurement(u32),
+    /// A label, jump, `HALT` or `WAIT`
+    ControlFlow,
+    /// A classical instruction such as `MOVE` or `ADD`
+    ClassicalInstruction,
+    /// A `RESET` after the first gate or measurement
+    LateReset,
+    /// A gate on a qubit after that qubit was measured
+    GateAfterMeasurement(u32),
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "instruction {}: ", self.index)?;
+        match &self.kind {
+            ViolationKind::UnresolvedQubit => write!(f, "qubit is not a fixed index"),
+            ViolationKind::UnknownQubit(qubit) => write!(f, "qubit {qubit} is not on the chip"),
+            ViolationKind::DeadQubit(qubit) => write!(f, "qubit {qubit} is dead"),
+            ViolationKind::UnsupportedEdge(a, b) => {
+                write!(f, "no live edge between qubits {a} and {b}")
+            }
+            ViolationKind::NonNativeGate(gate) => write!(f, "{gate} is not native"),
+            ViolationKind::NonNativeMeasurement(qubit) => {
+                write!(f, "measurement of qubit {qubit} is not native")
+            }
+            ViolationKind::ControlFlow => write!(f, "control flow is not allowed"),
+            ViolationKind::ClassicalInstruction => {
+                write!(f, "classical instructions are not allowed")
+            }
+            ViolationKind::LateReset => write!(f, "RESET must precede every gate and measurement"),
+            ViolationKind::GateAfterMeasurement(qubit) => {
+                write!(f, "gate on qubit {qubit} after it was measured")
+            }
+        }
+    }
+}
+
+/// Check that each qubit is on the chip and alive
+fn check_qubits(isa: &Isa, qubits: &[Qubit]) -> Result<Vec<u32>, ViolationKind> {
+    let qubits = fixed_indices(qubits).ok_or(ViolationKind::UnresolvedQubit)?;
+    for &qubit in &qubits {
+        match isa.qubit(qubit) {
+            None => return Err(ViolationKind::UnknownQubit(qubit)),
+            Some(q) if q.dead => return Err(ViolationKind::DeadQubit(qubit)),
+            Some(_) => {}
+        }
+    }
+    Ok(qubits)
+}
+
+/// Why a gate application is not native to the ISA, if it isn't
+pub(crate) fn gate_violation(isa: &Isa, gate: &Gate) -> Option<ViolationKind> {
+    let qubits = match check_qubits(isa, &gate.qubits) {
+        Ok(qubits) => qubits,
+        Err(kind) => return Some(kind),
+    };
+    if let [a, b] = qubits[..] {
+        if isa.edge(a, b).is_none_or(|edge| edge.dead) {
+            return Some(ViolationKind::UnsupportedEdge(a, b));
+        }
+    }
+    let parameters = real_parameters(&gate.parameters);
+    if gate.modifiers.is_empty() && isa.gate(&gate.name, &parameters, &qubits).is_some() {
+        None
+    } else {
+        let text = quil_rs::quil::Quil::to_quil_or_debug(gate);
+        Some(ViolationKind::NonNativeGate(text))
+    }
+}
+
+/// List the instructions of `program` that the chip cannot run as written: gates and
+/// measurements that are not among its natives, or that use missing or dead qubits and
+/// edges. Fails with [`Error::MissingIsa`] if the chip was not built from an ISA.
+pub fn check_native(program: &Program, chip: &Chip) -> Result<Vec<Violation>, Error> {
+    let isa = chip.require_isa()?;
+    let program = program.to_quil_rs()?;
+    let violations = program
+        .body_instructions()
+        .enumerate()
+        .filter_map(|(index, instruction)| {
+            let kind = match instruction {
+                Instruction::Gate(gate) => gate_violation(isa, gate),
+                Instruction::Measurement(measurement) => {
+                    match check_qubits(isa, std::slice::from_ref(&measurement.qubit)) {
+                        Err(kind) => Some(kind),
+                        Ok(qubits) => isa
+                            .measure(qubits[0], measurement.target.is_some())
+                            .is_none()
+                            .then_some(ViolationKind::NonNativeMeasurement(qubits[0])),
+                    }
+                }
+                _ => None,
+            };
+            kind.map(|kind| Violation { index, kind })
+        })
+        .collect();
+    Ok(violations)
+}
+
+/// List the instructions of `program` that break the restrictions
+/// [`compile_protoquil`](super::compile_protoquil) enforces on its output: no control
+/// flow or classical instructions, `RESET` only before the first gate or measurement,
+/// and no gate on a qubit once it has been measured.
+pub fn check_protoquil(program: &Program) -> Result<Vec<Violation>, Error> {
+    let program = program.to_quil_rs()?;
+    let mut started = false;
+    let mut measured = HashSet::new();
+    let mut violations = vec![];
+
+    for (index, instruction) in program.body_instructions().enumerate() {
+        let mut violate = |kind| violations.push(Violation { index, kind });
+        match instruction {
+            Instruction::Label(_)
+            | Instruction::Jump(_)
+            | Instruction::JumpWhen(_)
+            | Instruction::JumpUnless(_)
+            | Instruction::Halt
+            | Instruction::Wait => violate(ViolationKind::ControlFlow),
+            Instruction::Move(_)
+            | Instruction::Exchange(_)
+            | Instruction::Load(_)
+            | Instruction::Store(_)
+            | Instruction::Arithmetic(_)
+            | Instruction::BinaryLogic(_)
+            | Instruction::UnaryLogic(_)
+            | Instruction::Comparison(_)
+            | Instruction::Convert(_) => violate(ViolationKind::ClassicalInstruction),
+            Instruction::Reset(_) if started => violate(ViolationKind::LateReset),
+            Instruction::Gate(gate) => {
+                started = true;
+                if let Some(qubit) = gate
+                    .qubits
+                    .iter()
+                    .filter_map(fixed_index)
+                    .find(|qubit| measured.contains(qubit))
+                {
+                    violate(ViolationKind::GateAfterMeasurement(qubit));
+                }
+            }
+            Instruction::Measurement(measurement) => {
+                started = true;
+                measured.extend(fixed_index(&measurement.qubit));
+            }
+            _ => {}
+        }
+    }
+    Ok(violations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quilc::fixtures::{aspen_9, aspen_9_device};
+
+    #[test]
+    fn test_gate_violation() {
+        let isa = aspen_9_device().isa;
+        let program: quil_rs::Program = "RX(pi/2) 0\nXY(pi) 0 1\nH 0\nCZ 0 2\nRX(pi/2) 8\nCZ 0 q"
+            .parse()
+            .unwrap();
+        let violations = program
+            .body_instructions()
+            .map(|instruction| match instruction {
+                Instruction::Gate(gate) => gate_violation(&isa, gate),
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            violations,
+            vec![
+                None,
+                None,
+                Some(ViolationKind::NonNativeGate("H 0".to_string())),
+                Some(ViolationKind::UnsupportedEdge(0, 2)),
+                Some(ViolationKind::UnknownQubit(8)),
+                Some(ViolationKind::UnresolvedQubit),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_native() {
+        let chip = aspen_9();
+        let program: Program = "DECLARE ro BIT\nRX(pi/2) 0\nCNOT 0 1\nMEASURE 1 ro"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            check_native(&program, &chip).unwrap(),
+            vec![Violation {
+                index: 1,
+                kind: ViolationKind::NonNativeGate("CNOT 0 1".to_string())
+            }]
+        );
+    }
+
+    #[test]
+    fn test_check_protoquil() {
+        let program: Program =
+            "DECLARE ro BIT[2]\nRESET\nH 0\nMEASURE 0 ro[0]\nX 0\nRESET\nLABEL @end"
+                .parse()
+                .unwrap();
+        let kinds = check_protoquil(&program)
+            .unwrap()
+            .into_iter()
+            .map(|v| (v.index, v.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                (3, ViolationKind::GateAfterMeasurement(0)),
+                (4, ViolationKind::LateReset),
+                (5, ViolationKind::ControlFlow),
+            ]
+        );
+    }
+}
@@ -0,0 +1,411 @@
+//! A reusable compiler bound to a [`Chip`], configured with typed [`CompileOptions`]
+
+use std::{cell::OnceCell, collections::BTreeMap, fmt::Display};
+
+use rand::{seq::SliceRandom, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use super::{
+    compile_protoquil, compile_quil,
+    placement::{fixed_qubits, Placement},
+    Chip, CompilationResult, Error, Program,
+};
+
+/// How quilc chooses the initial logical-to-physical qubit mapping,
+/// as set by `PRAGMA INITIAL_REWIRING`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RewiringStrategy {
+    /// Logical qubit `n` starts on physical qubit `n`
+    Naive,
+    /// Place qubits to minimise the cost of the program's 2Q gates
+    Partial,
+    /// Place qubits greedily by interaction
+    Greedy,
+    /// Place qubits at random. When [`CompileOptions::seed`] is set the placement is
+    /// drawn in Rust so that the initial placement is reproducible.
+    Random,
+}
+
+impl Display for RewiringStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            RewiringStrategy::Naive => "NAIVE",
+            RewiringStrategy::Partial => "PARTIAL",
+            RewiringStrategy::Greedy => "GREEDY",
+            RewiringStrategy::Random => "RANDOM",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Options for a [`Compiler`]
+///
+/// There is no optimization level and there are no tuning overrides. quilc reads its
+/// compressor passes, arithmetic rewriting, addresser swap search and state-prep
+/// reductions from global variables in its Lisp image, and libquil's compile entry
+/// points neither take nor set them, so every compile uses quilc's defaults and none of
+/// them can be changed for a single call.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompileOptions {
+    /// Restrict the output to protoquil, as [`compile_protoquil`] does
+    pub protoquil: bool,
+    /// The initial placement of the program's qubits; `None` leaves the choice to quilc
+    /// (or to a `PRAGMA INITIAL_REWIRING` in the program itself)
+    pub initial_rewiring: Option<Placement>,
+    /// Whether to populate [`CompilationResult::metadata`]
+    pub return_metadata: bool,
+    /// Seed for the initial placement chosen by [`RewiringStrategy::Random`]. The
+    /// placement is drawn from a portable RNG seeded with this value, and quilc then
+    /// compiles from that fixed placement. A seed with any other `initial_rewiring`
+    /// fails with [`Error::UnsupportedOption`].
+    ///
+    /// This is a placement seed, not a compiler seed. libquil does not expose quilc's
+    /// Lisp random state, so quilc's addresser and compressor can still make different
+    /// choices: compiling the same program twice with the same seed starts from the same
+    /// placement but is not guaranteed to give the same output. Reproducible compiled
+    /// output is not available through libquil.
+    pub seed: Option<u64>,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self {
+            protoquil: false,
+            initial_rewiring: None,
+            return_metadata: true,
+            seed: None,
+        }
+    }
+}
+
+/// Compiles programs for a single [`Chip`]
+///
+/// ```no_run
+/// # use libquil_sys::quilc::{Chip, CompileOptions, Compiler, Program, RewiringStrategy};
+/// # fn main() -> Result<(), libquil_sys::quilc::Error> {
+/// let chip: Chip = std::fs::read_to_string("aspen-9-isa.json").unwrap().parse()?;
+/// let compiler = Compiler::new(&chip).with_options(CompileOptions {
+///     initial_rewiring: Some(RewiringStrategy::Partial.into()),
+///     ..Default::default()
+/// });
+/// let result = compiler.compile(&"H 0\nCNOT 0 1".parse::<Program>()?)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Compiler<'a> {
+    chip: &'a Chip,
+    options: CompileOptions,
+    #[cfg(feature = "cache")]
+    cache: Option<&'a super::cache::CompilationCache>,
+    /// The chip's live qubits in ascending order, computed on first use
+    live_qubits: OnceCell<Vec<u32>>,
+}
+
+impl<'a> Compiler<'a> {
+    /// A compiler for the chip with default [`CompileOptions`]
+    pub fn new(chip: &'a Chip) -> Self {
+        Self {
+            chip,
+            options: CompileOptions::default(),
+            #[cfg(feature = "cache")]
+            cache: None,
+            live_qubits: OnceCell::new(),
+        }
+    }
+
+    pub fn with_options(mut self, options: CompileOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Look up and store results in `cache`. Hits and misses are counted in
+    /// [`CompilationCache::stats`](super::cache::CompilationCache::stats).
+    #[cfg(feature = "cache")]
+    pub fn with_cache(mut self, cache: &'a super::cache::CompilationCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// A compiler for the same chip and cache with different options
+    pub(crate) fn with_replaced_options(&self, options: CompileOptions) -> Compiler<'a> {
+        Compiler {
+            chip: self.chip,
+            options,
+            #[cfg(feature = "cache")]
+            cache: self.cache,
+            live_qubits: self.live_qubits.clone(),
+        }
+    }
+
+    pub fn chip(&self) -> &Chip {
+        self.chip
+    }
+
+    pub fn options(&self) -> &CompileOptions {
+        &self.options
+    }
+
+    /// Compile the program according to the compiler's options, consulting the cache if set
+    pub fn compile(&self, program: &Program) -> Result<CompilationResult, Error> {
+        self.compile_with(program, &self.options)
+    }
+
+    /// Compile the program with the compiler's options, but with `seed` in place of
+    /// [`CompileOptions::seed`] for this call only. The seed fixes the initial placement,
+    /// not the compiled output.
+    pub fn compile_with_seed(
+        &self,
+        program: &Program,
+        seed: u64,
+    ) -> Result<CompilationResult, Error> {
+        let options = CompileOptions {
+            seed: Some(seed),
+            ..self.options.clone()
+        };
+        self.compile_with(program, &options)
+    }
+
+    fn compile_with(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+    ) -> Result<CompilationResult, Error> {
+        if options.seed.is_some()
+            && options.initial_rewiring != Some(RewiringStrategy::Random.into())
+        {
+            return Err(Error::UnsupportedOption("seed"));
+        }
+
+        #[cfg(feature = "cache")]
+        if let Some(cache) = self.cache {
+            if let Some(key) = super::cache::CompilationCache::key(program, self.chip, options)? {
+                if let Some(result) = cache.get(&key)? {
+                    return Ok(result);
+                }
+                let result = self.compile_uncached(program, options)?;
+                // A failure to store the result only costs a later compile
+                if let Err(error) = cache.insert(&key, &result) {
+                    log::warn!("failed to store compilation result in cache: {error}");
+                }
+                return Ok(result);
+            }
+        }
+        self.compile_uncached(program, options)
+    }
+
+    fn compile_uncached(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+    ) -> Result<CompilationResult, Error> {
+        let prepared = self.prepare(program, options)?;
+        let input = prepared.as_ref().map_or(program, |(_, prepared)| prepared);
+        let mut result = if options.protoquil {
+            compile_protoquil(input, self.chip)?
+        } else {
+            compile_quil(input, self.chip, options.return_metadata)?
+        };
+        if let Some((placement, _)) = &prepared {
+            result = placement.finish(result)?;
+        }
+        if !options.return_metadata {
+            result.metadata = None;
+        }
+        Ok(result)
+    }
+
+    /// Compile each program in turn. Results are in the same order as `programs`; a
+    /// failure compiling one program does not affect the others.
+    ///
+    /// The batch is not compiled in parallel. libquil runs quilc in a single Lisp image
+    /// that is not safe to call from several threads, so the programs are compiled one
+    /// after another and a batch takes as long as compiling each program in turn. Every
+    /// compile uses the chip specification libquil parsed when the [`Chip`] was built,
+    /// and what this compiler derives from the chip, such as its live qubits, is
+    /// computed once.
+    pub fn compile_batch(&self, programs: &[Program]) -> Vec<Result<CompilationResult, Error>> {
+        programs
+            .iter()
+            .map(|program| self.compile(program))
+            .collect()
+    }
+
+    fn live_qubits(&self) -> Result<&[u32], Error> {
+        if let Some(qubits) = self.live_qubits.get() {
+            return Ok(qubits);
+        }
+        let mut qubits = self
+            .chip
+            .require_isa()?
+            .qubits
+            .values()
+            .filter(|qubit| !qubit.dead)
+            .map(|qubit| qubit.id)
+            .collect::<Vec<_>>();
+        qubits.sort_unstable();
+        Ok(self.live_qubits.get_or_init(|| qubits))
+    }
+
+    /// Apply the placement options to the program, if any apply, returning the placement
+    /// to [finish](Placement::finish) the result with and the program to compile
+    fn prepare(
+        &self,
+        program: &Program,
+        options: &CompileOptions,
+    ) -> Result<Option<(Placement, Program)>, Error> {
+        let placement = match (&options.initial_rewiring, options.seed) {
+            (None, _) => return Ok(None),
+            (Some(Placement::Strategy(RewiringStrategy::Random)), Some(seed)) => {
+                Placement::Explicit(self.random_placement(program, seed)?)
+            }
+            (Some(placement), _) => placement.clone(),
+        };
+        let prepared = placement.apply(program, self.chip)?;
+        Ok(Some((placement, prepared)))
+    }
+
+    /// Draw a reproducible placement of the program's qubits onto the chip's live qubits
+    fn random_placement(&self, program: &Program, seed: u64) -> Result<BTreeMap<u32, u32>, Error> {
+        let mut physical = self.live_qubits()?.to_vec();
+        let logical = fixed_qubits(&program.to_quil_rs()?);
+        if logical.len() > physical.len() {
+            return Err(Error::InsufficientQubits {
+                required: logical.len(),
+                available: physical.len(),
+            });
+        }
+
+        physical.shuffle(&mut ChaCha8Rng::seed_from_u64(seed));
+        Ok(logical.into_iter().zip(physical).collect())
+    }
+}
+
+/// Compile each program for the chip with the same options, one after another; see
+/// [`Compiler::compile_batch`]
+pub fn compile_batch(
+    programs: &[Program],
+    chip: &Chip,
+    options: CompileOptions,
+) -> Vec<Result<CompilationResult, Error>> {
+    Compiler::new(chip)
+        .with_options(options)
+        .compile_batch(programs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quilc::fixtures::{aspen_9, aspen_9_device};
+
+    #[test]
+    fn test_compile_with_options() {
+        let chip = aspen_9();
+        let program: Program = "H 0\nCNOT 0 1".parse().unwrap();
+
+        let result = Compiler::new(&chip)
+            .with_options(CompileOptions {
+                protoquil: true,
+                initial_rewiring: Some(RewiringStrategy::Naive.into()),
+                ..Default::default()
+            })
+            .compile(&program)
+            .unwrap();
+        assert_eq!(&result.metadata.unwrap().final_rewiring[..2], &[0, 1]);
+
+        let result = Compiler::new(&chip)
+            .with_options(CompileOptions {
+                protoquil: true,
+                initial_rewiring: Some(Placement::Explicit([(0, 11), (1, 10)].into())),
+                ..Default::default()
+            })
+            .compile(&program)
+            .unwrap();
+        assert_eq!(&result.metadata.unwrap().final_rewiring[..2], &[11, 10]);
+
+        let result = Compiler::new(&chip)
+            .with_options(CompileOptions {
+                protoquil: true,
+                return_metadata: false,
+                ..Default::default()
+            })
+            .compile(&program)
+            .unwrap();
+        assert!(result.metadata.is_none());
+    }
+
+    #[test]
+    fn test_compile_batch() {
+        let chip = aspen_9();
+        let programs = ["H 0\nCNOT 0 1", "X 0", "CNOT 0 1\nCNOT 1 2", "GATE"]
+            .iter()
+            .map(|text| text.parse::<Program>().unwrap())
+            .collect::<Vec<_>>();
+        let results = compile_batch(&programs, &chip, CompileOptions::default());
+
+        assert_eq!(results.len(), 4);
+        assert!(results[..3].iter().all(Result::is_ok));
+        assert!(matches!(results[3], Err(Error::CompileQuil(_))));
+    }
+
+    #[test]
+    fn test_seeded_random_placement() {
+        let live = aspen_9_device()
+            .isa
+            .qubits
+            .values()
+            .filter(|q| !q.dead)
+            .map(|q| q.id)
+            .collect::<Vec<_>>();
+
+        let chip = aspen_9();
+        let compiler = Compiler::new(&chip);
+        let program: Program = "CNOT 0 1\nCNOT 1 2".parse().unwrap();
+        let placement = compiler.random_placement(&program, 7).unwrap();
+        assert_eq!(placement, compiler.random_placement(&program, 7).unwrap());
+        assert_eq!(placement.len(), 3);
+        assert!(placement.values().all(|q| live.contains(q)));
+    }
+
+    #[test]
+    fn test_identical_seeds_give_identical_placement() {
+        let chip = aspen_9();
+        let program: Program = "H 0\nCNOT 0 1\nCNOT 0 2\nCNOT 1 2".parse().unwrap();
+        let compiler = Compiler::new(&chip).with_options(CompileOptions {
+            initial_rewiring: Some(RewiringStrategy::Random.into()),
+            ..Default::default()
+        });
+        let options = CompileOptions {
+            seed: Some(42),
+            ..compiler.options().clone()
+        };
+
+        // The seed fixes the program quilc is given, not quilc's own choices
+        let prepare = || compiler.prepare(&program, &options).unwrap().unwrap();
+        let (placement, prepared) = prepare();
+        assert_eq!(placement, prepare().0);
+        assert_eq!(
+            prepared.to_string().unwrap(),
+            prepare().1.to_string().unwrap()
+        );
+        compiler.compile_with_seed(&program, 42).unwrap();
+        // The seed was scoped to the call above
+        assert_eq!(compiler.options().seed, None);
+    }
+
+    #[test]
+    fn test_seed_requires_random_placement() {
+        let chip = aspen_9();
+        let program: Program = "X 0".parse().unwrap();
+        for initial_rewiring in [None, Some(RewiringStrategy::Partial.into())] {
+            let compiler = Compiler::new(&chip).with_options(CompileOptions {
+                initial_rewiring,
+                ..Default::default()
+            });
+            assert!(matches!(
+                compiler.compile_with_seed(&program, 42),
+                Err(Error::UnsupportedOption("seed"))
+            ));
+        }
+    }
+}
@@ -0,0 +1,353 @@
+//! Typed construction and validation of the block pragmas quilc honours:
+//! `PRESERVE_BLOCK`, `COMMUTING_BLOCKS` and the `BLOCK`s inside them.
+
+use std::{fmt::Display, str::FromStr};
+
+use quil_rs::instruction::Instruction;
+
+use super::Program;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum Error {
+    #[error("{found} at instruction {index} closes {expected:?}")]
+    Mismatched {
+        index: usize,
+        expected: Option<Directive>,
+        found: String,
+    },
+    #[error("{0} is never closed")]
+    Unclosed(Directive),
+    #[error("{inner} at instruction {index} cannot be nested in {outer:?}")]
+    InvalidNesting {
+        index: usize,
+        inner: Directive,
+        outer: Option<Directive>,
+    },
+    #[error("instruction {index} must be inside a BLOCK within COMMUTING_BLOCKS")]
+    OutsideBlock { index: usize },
+}
+
+/// A block directive
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Directive {
+    /// Instructions quilc passes through without compiling them
+    PreserveBlock,
+    /// A sequence of `BLOCK`s that quilc may reorder
+    CommutingBlocks,
+    /// One of the blocks of a `COMMUTING_BLOCKS`
+    Block,
+}
+
+impl Directive {
+    fn name(&self) -> &'static str {
+        match self {
+            Directive::PreserveBlock => "PRESERVE_BLOCK",
+            Directive::CommutingBlocks => "COMMUTING_BLOCKS",
+            Directive::Block => "BLOCK",
+        }
+    }
+
+    /// The directive opened or closed by a pragma, and whether it closes it
+    fn from_pragma(name: &str) -> Option<(Directive, bool)> {
+        let (name, end) = match name.strip_prefix("END_") {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        let directive = match name {
+            "PRESERVE_BLOCK" => Directive::PreserveBlock,
+            "COMMUTING_BLOCKS" => Directive::CommutingBlocks,
+            "BLOCK" => Directive::Block,
+            _ => return None,
+        };
+        Some((directive, end))
+    }
+
+    /// Whether this directive may open directly inside `outer`
+    fn may_nest_in(&self, outer: Option<Directive>) -> bool {
+        match (self, outer) {
+            (_, Some(Directive::PreserveBlock)) => false,
+            (Directive::Block, outer) => outer == Some(Directive::CommutingBlocks),
+            (_, Some(Directive::CommutingBlocks)) => false,
+            _ => true,
+        }
+    }
+}
+
+impl Display for Directive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Tracks open directives while walking a program
+#[derive(Debug, Default)]
+struct Nesting {
+    open: Vec<Directive>,
+}
+
+impl Nesting {
+    fn begin(&mut self, index: usize, directive: Directive) -> Result<(), Error> {
+        let outer = self.open.last().copied();
+        if !directive.may_nest_in(outer) {
+            return Err(Error::InvalidNesting {
+                index,
+                inner: directive,
+                outer,
+            });
+        }
+        self.open.push(directive);
+        Ok(())
+    }
+
+    fn end(&mut self, index: usize, directive: Directive) -> Result<(), Error> {
+        let expected = self.open.last().copied();
+        if expected != Some(directive) {
+            return Err(Error::Mismatched {
+                index,
+                expected,
+                found: format!("END_{directive}"),
+            });
+        }
+        self.open.pop();
+        Ok(())
+    }
+
+    fn instruction(&self, index: usize) -> Result<(), Error> {
+        if self.open.last() == Some(&Directive::CommutingBlocks) {
+            return Err(Error::OutsideBlock { index });
+        }
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<(), Error> {
+        match self.open.last() {
+            Some(&directive) => Err(Error::Unclosed(directive)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Check that the block directives in a program are balanced and correctly nested.
+/// Instruction indices in errors count the program's body instructions.
+pub fn validate(program: &quil_rs::Program) -> Result<(), Error> {
+    let mut nesting = Nesting::default();
+    for (index, instruction) in program.body_instructions().enumerate() {
+        match instruction {
+            Instruction::Pragma(pragma) => match Directive::from_pragma(&pragma.name) {
+                Some((directive, false)) => nesting.begin(index, directive)?,
+                Some((directive, true)) => nesting.end(index, directive)?,
+                None => nesting.instruction(index)?,
+            },
+            _ => nesting.instruction(index)?,
+        }
+    }
+    nesting.finish()
+}
+
+/// Builds Quil text containing block directives, checking them as it goes. Instruction
+/// indices in errors count body instructions, as [`validate`] does, so they point into
+/// the built program.
+///
+/// ```no_run
+/// # use libquil_sys::quilc::directives::ProgramBuilder;
+/// let mut builder = ProgramBuilder::new();
+/// builder.push("H 0").preserve("RX(pi/2) 1\nRX(pi/2) 1")?;
+/// builder.commuting_blocks(&["RZ(0.1) 0", "RZ(0.2) 1"])?;
+/// let program = builder.build()?;
+/// # Ok::<(), libquil_sys::quilc::Error>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct ProgramBuilder {
+    lines: Vec<String>,
+    /// The number of body instructions in `lines`
+    instructions: usize,
+    nesting: Nesting,
+}
+
+/// The number of body instructions in a fragment of Quil. A fragment quil-rs cannot
+/// parse is counted by its non-empty lines; [`ProgramBuilder::build`] reports the error.
+fn count_instructions(quil: &str) -> usize {
+    match quil.parse::<quil_rs::Program>() {
+        Ok(program) => program.body_instructions().count(),
+        Err(_) => quil.lines().filter(|line| !line.trim().is_empty()).count(),
+    }
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append Quil instructions
+    pub fn push(&mut self, quil: &str) -> &mut Self {
+        self.lines.push(quil.trim_end().to_string());
+        self.instructions += count_instructions(quil);
+        self
+    }
+
+    /// Open a directive
+    pub fn begin(&mut self, directive: Directive) -> Result<&mut Self, Error> {
+        self.nesting.begin(self.instructions, directive)?;
+        self.lines.push(format!("PRAGMA {directive}"));
+        self.instructions += 1;
+        Ok(self)
+    }
+
+    /// Close the innermost directive, which must be `directive`
+    pub fn end(&mut self, directive: Directive) -> Result<&mut Self, Error> {
+        self.nesting.end(self.instructions, directive)?;
+        self.lines.push(format!("PRAGMA END_{directive}"));
+        self.instructions += 1;
+        Ok(self)
+    }
+
+    /// Append `quil` wrapped in `PRESERVE_BLOCK`
+    pub fn preserve(&mut self, quil: &str) -> Result<&mut Self, Error> {
+        self.begin(Directive::PreserveBlock)?;
+        self.push(quil);
+        self.end(Directive::PreserveBlock)
+    }
+
+    /// Append a `COMMUTING_BLOCKS` with one `BLOCK` per entry of `blocks`
+    pub fn commuting_blocks(&mut self, blocks: &[&str]) -> Result<&mut Self, Error> {
+        self.begin(Directive::CommutingBlocks)?;
+        for block in blocks {
+            self.begin(Directive::Block)?;
+            self.push(block);
+            self.end(Directive::Block)?;
+        }
+        self.end(Directive::CommutingBlocks)
+    }
+
+    /// The Quil text, if every directive has been closed
+    pub fn to_quil(&self) -> Result<String, Error> {
+        self.nesting.finish()?;
+        Ok(self.lines.join("\n") + "\n")
+    }
+
+    /// Parse the Quil text with quilc, checking the directives across the whole program
+    pub fn build(&self) -> Result<Program, super::Error> {
+        let text = self.to_quil()?;
+        let program = Program::from_str(&text)?;
+        validate(&program.to_quil_rs()?)?;
+        Ok(program)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quilc::fixtures::aspen_9;
+    use crate::quilc::{compile_program, Placement, RewiringStrategy};
+
+    #[test]
+    fn test_validate() {
+        let program: quil_rs::Program = "PRAGMA COMMUTING_BLOCKS\nPRAGMA BLOCK\nX 0\nPRAGMA END_BLOCK\nPRAGMA END_COMMUTING_BLOCKS"
+            .parse()
+            .unwrap();
+        validate(&program).unwrap();
+
+        let program: quil_rs::Program = "PRAGMA PRESERVE_BLOCK\nX 0".parse().unwrap();
+        assert_eq!(
+            validate(&program),
+            Err(Error::Unclosed(Directive::PreserveBlock))
+        );
+
+        let program: quil_rs::Program = "PRAGMA COMMUTING_BLOCKS\nX 0\nPRAGMA END_COMMUTING_BLOCKS"
+            .parse()
+            .unwrap();
+        assert_eq!(validate(&program), Err(Error::OutsideBlock { index: 1 }));
+
+        let program: quil_rs::Program = "PRAGMA PRESERVE_BLOCK\nPRAGMA END_BLOCK".parse().unwrap();
+        assert_eq!(
+            validate(&program),
+            Err(Error::Mismatched {
+                index: 1,
+                expected: Some(Directive::PreserveBlock),
+                found: "END_BLOCK".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_builder_nesting() {
+        let mut builder = ProgramBuilder::new();
+        assert_eq!(
+            builder.begin(Directive::Block).err(),
+            Some(Error::InvalidNesting {
+                index: 0,
+                inner: Directive::Block,
+                outer: None
+            })
+        );
+
+        builder.begin(Directive::PreserveBlock).unwrap();
+        assert!(matches!(
+            builder.begin(Directive::CommutingBlocks),
+            Err(Error::InvalidNesting { .. })
+        ));
+        assert_eq!(
+            builder.to_quil(),
+            Err(Error::Unclosed(Directive::PreserveBlock))
+        );
+        builder.push("X 0").end(Directive::PreserveBlock).unwrap();
+        assert_eq!(
+            builder.to_quil().unwrap(),
+            "PRAGMA PRESERVE_BLOCK\nX 0\nPRAGMA END_PRESERVE_BLOCK\n"
+        );
+    }
+
+    #[test]
+    fn test_builder_indices_count_instructions() {
+        let mut builder = ProgramBuilder::new();
+        // One push of three instructions, one of which is a declaration
+        builder.push("DECLARE ro BIT\nH 0\nCNOT 0 1");
+        assert_eq!(
+            builder.end(Directive::Block).err(),
+            Some(Error::Mismatched {
+                index: 2,
+                expected: None,
+                found: "END_BLOCK".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_preserved_block_survives_compilation() {
+        let chip = aspen_9();
+        let preserved = "RX(pi/2) 1\nRX(pi/2) 1\nRZ(0.5) 1";
+        let mut builder = ProgramBuilder::new();
+        builder.push("H 0\nCNOT 0 1").preserve(preserved).unwrap();
+        builder.push("CNOT 1 0");
+        // Keep logical qubits on their physical qubits so the block's indices are meaningful
+        let program = Placement::from(RewiringStrategy::Naive)
+            .apply(&builder.build().unwrap(), &chip)
+            .unwrap();
+
+        let compiled = compile_program(&program, &chip)
+            .unwrap()
+            .program
+            .to_quil_rs()
+            .unwrap();
+        validate(&compiled).unwrap();
+        let instructions = compiled.body_instructions().collect::<Vec<_>>();
+        let is_pragma = |instruction: &Instruction, name: &str| matches!(instruction, Instruction::Pragma(pragma) if pragma.name == name);
+        let start = instructions
+            .iter()
+            .position(|i| is_pragma(i, "PRESERVE_BLOCK"))
+            .unwrap();
+        let end = instructions
+            .iter()
+            .position(|i| is_pragma(i, "END_PRESERVE_BLOCK"))
+            .unwrap();
+
+        let expected: quil_rs::Program = preserved.parse().unwrap();
+        assert_eq!(
+            instructions[start + 1..end]
+                .iter()
+                .map(|i| (*i).clone())
+                .collect::<Vec<_>>(),
+            expected.body_instructions().cloned().collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,246 @@
+//! Translating programs into an arbitrary set of native gates
+
+use std::collections::BTreeSet;
+
+use quil_rs::instruction::{Gate, Instruction};
+
+use super::{
+    check::gate_violation,
+    compile_program,
+    isa::{self, Argument, GateInfo, Isa, MeasureInfo, Operation, Parameter},
+    placement::fixed_qubits,
+    Chip, Error, Placement, Program, RewiringStrategy,
+};
+
+/// A set of native gates to translate programs into
+///
+/// ```
+/// # use libquil_sys::quilc::{isa::Parameter, GateSet};
+/// use std::f64::consts::FRAC_PI_2;
+/// let gates = GateSet::new()
+///     .with_1q("RX", vec![Parameter::Value(FRAC_PI_2)])
+///     .with_1q("RX", vec![Parameter::Value(-FRAC_PI_2)])
+///     .with_1q("RZ", vec![Parameter::Name("_".to_string())])
+///     .with_2q("CZ", vec![]);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GateSet {
+    one_qubit: Vec<GateInfo>,
+    two_qubit: Vec<GateInfo>,
+}
+
+impl GateSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a single-qubit gate. A named parameter such as `"_"` accepts any angle.
+    pub fn with_1q(mut self, operator: &str, parameters: Vec<Parameter>) -> Self {
+        self.one_qubit.push(gate_info(operator, parameters, 1));
+        self
+    }
+
+    /// Add a two-qubit gate, applicable to any pair of qubits in either order
+    pub fn with_2q(mut self, operator: &str, parameters: Vec<Parameter>) -> Self {
+        self.two_qubit.push(gate_info(operator, parameters, 2));
+        self
+    }
+
+    /// An all-to-all ISA on `qubits` with exactly these gates plus measurement
+    fn isa(&self, qubits: &[u32]) -> Isa {
+        let mut isa = Isa::default();
+        for &id in qubits {
+            let measure = |target: Option<&str>| {
+                Operation::Measure(MeasureInfo {
+                    operator: "MEASURE".to_string(),
+                    qubit: Argument::wildcard(),
+                    target: target.map(str::to_string),
+                    duration: None,
+                    fidelity: None,
+                })
+            };
+            let gates = self
+                .one_qubit
+                .iter()
+                .cloned()
+                .map(Operation::Gate)
+                .chain([measure(Some("_")), measure(None)])
+                .collect();
+            isa.insert_qubit(isa::Qubit {
+                id,
+                dead: false,
+                gates,
+            });
+        }
+        for (i, &a) in qubits.iter().enumerate() {
+            for &b in &qubits[i + 1..] {
+                isa.insert_edge(isa::Edge {
+                    ids: [a, b],
+                    dead: false,
+                    gates: self
+                        .two_qubit
+                        .iter()
+                        .cloned()
+                        .map(Operation::Gate)
+                        .collect(),
+                });
+            }
+        }
+        isa
+    }
+
+    fn contains_operator(&self, operator: &str) -> bool {
+        self.one_qubit
+            .iter()
+            .chain(&self.two_qubit)
+            .any(|gate| gate.operator == operator)
+    }
+}
+
+fn gate_info(operator: &str, parameters: Vec<Parameter>, arity: usize) -> GateInfo {
+    GateInfo {
+        operator: operator.to_string(),
+        parameters,
+        arguments: vec![Argument::wildcard(); arity],
+        duration: None,
+        fidelity: None,
+    }
+}
+
+/// Whether the gate application is one of the ISA's natives
+fn is_native(isa: &Isa, gate: &Gate) -> bool {
+    gate_violation(isa, gate).is_none()
+}
+
+/// Whether `name` appears in `message` as a whole word rather than as part of a longer
+/// word, so that a gate such as `X` is not blamed for every message containing an X
+fn names_gate(message: &str, name: &str) -> bool {
+    message
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+        .any(|word| word == name)
+}
+
+/// Translate a program into `gates`, keeping its qubit indices.
+///
+/// The program is compiled for a synthetic chip connecting every qubit the program uses
+/// to every other, so no routing takes place. If quilc leaves gates outside the set in
+/// its output, or its error names gates of the program that are outside the set,
+/// [`Error::Untranslatable`] names them; any other compile error is returned as is.
+pub fn translate_to_gateset(program: &Program, gates: GateSet) -> Result<Program, Error> {
+    let input = program.to_quil_rs()?;
+    let isa = gates.isa(&fixed_qubits(&input));
+    let chip = Chip::try_from(&isa)?;
+    let placement = Placement::from(RewiringStrategy::Naive);
+
+    let result = match compile_program(&placement.apply(program, &chip)?, &chip) {
+        Ok(result) => result,
+        Err(error) => {
+            // Only blame the gates quilc's error names
+            let message = error.to_string();
+            let missing = input
+                .body_instructions()
+                .filter_map(|instruction| match instruction {
+                    Instruction::Gate(gate)
+                        if !gates.contains_operator(&gate.name)
+                            && names_gate(&message, &gate.name) =>
+                    {
+                        Some(gate.name.clone())
+                    }
+                    _ => None,
+                })
+                .collect::<BTreeSet<_>>();
+            return Err(if missing.is_empty() {
+                error
+            } else {
+                Error::Untranslatable(missing.into_iter().collect())
+            });
+        }
+    };
+
+    let remaining = result
+        .program
+        .to_quil_rs()?
+        .body_instructions()
+        .filter_map(|instruction| match instruction {
+            Instruction::Gate(gate) if !is_native(&isa, gate) => Some(gate.name.clone()),
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>();
+    if !remaining.is_empty() {
+        return Err(Error::Untranslatable(remaining.into_iter().collect()));
+    }
+    Ok(result.program)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::FRAC_PI_2;
+
+    use super::*;
+
+    fn rigetti() -> GateSet {
+        GateSet::new()
+            .with_1q("RX", vec![Parameter::Value(FRAC_PI_2)])
+            .with_1q("RX", vec![Parameter::Value(-FRAC_PI_2)])
+            .with_1q("RZ", vec![Parameter::Name("_".to_string())])
+            .with_2q("CZ", vec![])
+    }
+
+    #[test]
+    fn test_synthetic_isa() {
+        let isa = rigetti().isa(&[0, 3, 5]);
+        assert_eq!(isa.qubits.len(), 3);
+        assert_eq!(isa.edges.len(), 3);
+        assert!(isa.gate("CZ", &[], &[5, 0]).is_some());
+        assert!(isa.gate("RZ", &[Some(0.3)], &[3]).is_some());
+        assert!(isa.gate("RX", &[Some(0.3)], &[3]).is_none());
+        assert!(isa.measure(5, true).is_some());
+
+        let program: quil_rs::Program = "X 0\nRZ(pi) 0\nDAGGER RZ(pi) 0".parse().unwrap();
+        let natives = program
+            .body_instructions()
+            .map(|instruction| match instruction {
+                Instruction::Gate(gate) => is_native(&isa, gate),
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(natives, vec![false, true, false]);
+    }
+
+    #[test]
+    fn test_translate_to_gateset() {
+        let program: Program = "H 0\nCNOT 0 5\nMEASURE 5".parse().unwrap();
+        let translated = translate_to_gateset(&program, rigetti()).unwrap();
+        let translated = translated.to_quil_rs().unwrap();
+        assert_eq!(fixed_qubits(&translated), vec![0, 5]);
+
+        let isa = rigetti().isa(&[0, 5]);
+        assert!(translated
+            .body_instructions()
+            .all(|instruction| match instruction {
+                Instruction::Gate(gate) => is_native(&isa, gate),
+                _ => true,
+            }));
+    }
+
+    #[test]
+    fn test_untranslatable() {
+        // Without an entangling gate, CNOT cannot be expressed
+        let gates = GateSet::new().with_1q("RZ", vec![Parameter::Name("_".to_string())]);
+        let program: Program = "RZ(0.1) 0\nCNOT 0 1".parse().unwrap();
+        match translate_to_gateset(&program, gates) {
+            Err(Error::Untranslatable(names)) => assert_eq!(names, vec!["CNOT".to_string()]),
+            other => panic!("expected CNOT to be untranslatable, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_names_gate() {
+        let message = "error when calling quilc_compile_quil: no way to compile CNOT 0 1";
+        assert!(names_gate(message, "CNOT"));
+        assert!(!names_gate(message, "X"));
+        assert!(!names_gate(message, "C"));
+        assert!(names_gate("cannot apply (H 0)", "H"));
+        assert!(!names_gate("RX(pi/2) 0 is not native", "X"));
+    }
+}
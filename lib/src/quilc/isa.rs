@@ -0,0 +1,621 @@
+//! Typed representation of the ISA documents accepted by quilc's
+//! `quilc_parse_chip_spec_isa_json` (see `data/aspen-9-isa.json`).
+
+use std::{collections::BTreeMap, str::FromStr};
+
+use quil_rs::expression::Expression;
+use serde_json::{json, Map, Value};
+
+use super::{
+    json::{self as document, Node, Object},
+    validation::{IsaError, Reason},
+};
+
+/// The value of each gate parameter, as [`Isa::gate`] takes them: `None` for one that
+/// does not evaluate to a real number
+pub(crate) fn real_parameters(parameters: &[Expression]) -> Vec<Option<f64>> {
+    parameters
+        .iter()
+        .map(|p| p.clone().into_simplified().to_real().ok())
+        .collect()
+}
+
+/// The top-level document quilc reads a chip specification from
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TargetDevice {
+    /// The document type tag, `"TargetDevice"` in documents exported by pyQuil
+    pub device_type: Option<String>,
+    pub isa: Isa,
+    /// Benchmarks quilc reads fidelities from, keyed like the ISA: `"1Q"` maps qubit ids
+    /// and `"2Q"` maps edge keys to their measured values
+    pub specs: Map<String, Value>,
+}
+
+impl FromStr for TargetDevice {
+    type Err = IsaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TargetDevice::read(Node::root(&document::parse(s)?))
+    }
+}
+
+impl From<Isa> for TargetDevice {
+    fn from(isa: Isa) -> Self {
+        Self {
+            device_type: None,
+            isa,
+            specs: Default::default(),
+        }
+    }
+}
+
+impl TargetDevice {
+    /// A copy of this device with the ISA restricted as by [`Isa::restrict_to`], and
+    /// the specs of dead qubits and edges dropped
+    pub fn restrict_to(&self, qubits: &[u32]) -> Result<TargetDevice, super::Error> {
+        Ok(self.with_isa(self.isa.restrict_to(qubits)?))
+    }
+
+    /// A copy of this device with the given edges marked dead, as by
+    /// [`Isa::without_edges`], and their specs dropped
+    pub fn without_edges(&self, edges: &[(u32, u32)]) -> Result<TargetDevice, super::Error> {
+        Ok(self.with_isa(self.isa.without_edges(edges)?))
+    }
+
+    /// This device with `isa` in place of its own, keeping only the `"1Q"` and `"2Q"`
+    /// specs of the qubits and edges alive in `isa`
+    fn with_isa(&self, isa: Isa) -> TargetDevice {
+        let mut specs = self.specs.clone();
+        if let Some(Value::Object(qubits)) = specs.get_mut("1Q") {
+            qubits.retain(|id, _| isa.qubits.get(id).is_some_and(|qubit| !qubit.dead));
+        }
+        if let Some(Value::Object(edges)) = specs.get_mut("2Q") {
+            edges.retain(|id, _| isa.edges.get(id).is_some_and(|edge| !edge.dead));
+        }
+        TargetDevice {
+            device_type: self.device_type.clone(),
+            isa,
+            specs,
+        }
+    }
+}
+
+/// The qubits and edges of a chip, keyed the way quilc expects them:
+/// `"<id>"` for qubits and `"<low>-<high>"` for edges.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Isa {
+    pub qubits: BTreeMap<String, Qubit>,
+    pub edges: BTreeMap<String, Edge>,
+}
+
+impl Isa {
+    /// Get the qubit with the given id
+    pub fn qubit(&self, id: u32) -> Option<&Qubit> {
+        self.qubits.get(&id.to_string())
+    }
+
+    /// Get the edge between the two qubits, in either order
+    pub fn edge(&self, a: u32, b: u32) -> Option<&Edge> {
+        self.edges.get(&edge_key(a, b))
+    }
+
+    /// Insert a qubit under its canonical key
+    pub fn insert_qubit(&mut self, qubit: Qubit) {
+        self.qubits.insert(qubit.id.to_string(), qubit);
+    }
+
+    /// Insert an edge under its canonical key
+    pub fn insert_edge(&mut self, edge: Edge) {
+        self.edges.insert(edge_key(edge.ids[0], edge.ids[1]), edge);
+    }
+
+    /// The native gate matching an application of `operator` to `qubits`. Parameters that
+    /// can't be evaluated (`None`) only match named parameters such as `"_"`.
+    pub fn gate(
+        &self,
+        operator: &str,
+        parameters: &[Option<f64>],
+        qubits: &[u32],
+    ) -> Option<&GateInfo> {
+        let gates = match qubits {
+            [qubit] => &self.qubit(*qubit)?.gates,
+            [a, b] => &self.edge(*a, *b)?.gates,
+            _ => return None,
+        };
+        gates.iter().find_map(|operation| match operation {
+            Operation::Gate(gate)
+                if gate.operator == operator
+                    && gate.matches_parameters(parameters)
+                    && gate.matches_arguments(qubits) =>
+            {
+                Some(gate)
+            }
+            _ => None,
+        })
+    }
+
+    /// The native measurement of `qubit`, either into memory or discarding the result
+    pub fn measure(&self, qubit: u32, into_memory: bool) -> Option<&MeasureInfo> {
+        self.qubit(qubit)?
+            .gates
+            .iter()
+            .find_map(|operation| match operation {
+                Operation::Measure(measure) if measure.target.is_some() == into_memory => {
+                    Some(measure)
+                }
+                _ => None,
+            })
+    }
+
+    /// A copy of this ISA in which every qubit outside `qubits`, and every edge
+    /// touching such a qubit, is marked dead
+    pub fn restrict_to(&self, qubits: &[u32]) -> Result<Isa, super::Error> {
+        if let Some(&id) = qubits.iter().find(|&&id| self.qubit(id).is_none()) {
+            return Err(super::Error::UnknownQubit(id));
+        }
+
+        let mut isa = self.clone();
+        for qubit in isa.qubits.values_mut() {
+            qubit.dead |= !qubits.contains(&qubit.id);
+        }
+        for edge in isa.edges.values_mut() {
+            edge.dead |= edge.ids.iter().any(|id| !qubits.contains(id));
+        }
+        Ok(isa)
+    }
+
+    /// A copy of this ISA in which the given edges are marked dead
+    pub fn without_edges(&self, edges: &[(u32, u32)]) -> Result<Isa, super::Error> {
+        let mut isa = self.clone();
+        for &(a, b) in edges {
+            isa.edges
+                .get_mut(&edge_key(a, b))
+                .ok_or(super::Error::UnknownEdge(a, b))?
+                .dead = true;
+        }
+        Ok(isa)
+    }
+}
+
+/// The key quilc uses for the edge between `a` and `b`
+pub fn edge_key(a: u32, b: u32) -> String {
+    format!("{}-{}", a.min(b), a.max(b))
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Qubit {
+    pub id: u32,
+    pub dead: bool,
+    pub gates: Vec<Operation>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Edge {
+    pub ids: [u32; 2],
+    pub dead: bool,
+    pub gates: Vec<Operation>,
+}
+
+/// A native operation supported on a qubit or edge
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operation {
+    Gate(GateInfo),
+    Measure(MeasureInfo),
+}
+
+impl Operation {
+    pub fn operator(&self) -> &str {
+        match self {
+            Operation::Gate(gate) => &gate.operator,
+            Operation::Measure(measure) => &measure.operator,
+        }
+    }
+
+    pub fn duration(&self) -> Option<f64> {
+        match self {
+            Operation::Gate(gate) => gate.duration,
+            Operation::Measure(measure) => measure.duration,
+        }
+    }
+
+    pub fn fidelity(&self) -> Option<f64> {
+        match self {
+            Operation::Gate(gate) => gate.fidelity,
+            Operation::Measure(measure) => measure.fidelity,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GateInfo {
+    pub operator: String,
+    pub parameters: Vec<Parameter>,
+    pub arguments: Vec<Argument>,
+    pub duration: Option<f64>,
+    pub fidelity: Option<f64>,
+}
+
+impl GateInfo {
+    fn matches_parameters(&self, parameters: &[Option<f64>]) -> bool {
+        self.parameters.len() == parameters.len()
+            && self
+                .parameters
+                .iter()
+                .zip(parameters)
+                .all(|(expected, actual)| match (expected, actual) {
+                    (Parameter::Name(_), _) => true,
+                    (Parameter::Value(expected), Some(actual)) => {
+                        let difference = (expected - actual).rem_euclid(std::f64::consts::TAU);
+                        difference.min(std::f64::consts::TAU - difference) < 1e-9
+                    }
+                    (Parameter::Value(_), None) => false,
+                })
+    }
+
+    fn matches_arguments(&self, qubits: &[u32]) -> bool {
+        self.arguments.len() == qubits.len()
+            && self
+                .arguments
+                .iter()
+                .zip(qubits)
+                .all(|(argument, qubit)| match argument {
+                    Argument::Qubit(id) => id == qubit,
+                    Argument::Name(_) => true,
+                })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeasureInfo {
+    pub operator: String,
+    pub qubit: Argument,
+    /// `Some("_")` for a measurement into memory, `None` for a bare `MEASURE q`
+    pub target: Option<String>,
+    pub duration: Option<f64>,
+    pub fidelity: Option<f64>,
+}
+
+/// A gate parameter: either a fixed angle or a name (`"_"` or `"theta"`) matching any value
+#[derive(Clone, Debug, PartialEq)]
+pub enum Parameter {
+    Value(f64),
+    Name(String),
+}
+
+/// A gate argument: either a fixed qubit or `"_"` matching the qubit(s) of the enclosing entry
+#[derive(Clone, Debug, PartialEq)]
+pub enum Argument {
+    Qubit(u32),
+    Name(String),
+}
+
+impl Argument {
+    pub fn wildcard() -> Self {
+        Argument::Name("_".to_string())
+    }
+}
+
+// quilc's JSON form of the documents above. Fields quilc doesn't know are ignored
+// when reading, and absent or `null` optional fields take their defaults.
+
+impl TargetDevice {
+    /// The document as JSON, in the form quilc reads it
+    pub fn to_json(&self) -> String {
+        self.to_value().to_string()
+    }
+
+    fn read(node: Node) -> Result<Self, IsaError> {
+        let object = node.object()?;
+        Ok(Self {
+            device_type: object.read("_type", |node| node.string())?,
+            isa: Isa::read(object.required("isa")?)?,
+            specs: object
+                .read("specs", |node| Ok(node.object()?.fields().clone()))?
+                .unwrap_or_default(),
+        })
+    }
+
+    fn to_value(&self) -> Value {
+        let mut object = Map::new();
+        if let Some(device_type) = &self.device_type {
+            object.insert("_type".to_string(), device_type.clone().into());
+        }
+        object.insert("isa".to_string(), self.isa.to_value());
+        object.insert("specs".to_string(), Value::Object(self.specs.clone()));
+        Value::Object(object)
+    }
+}
+
+impl Isa {
+    fn read(node: Node) -> Result<Self, IsaError> {
+        let object = node.object()?;
+        let mut isa = Isa::default();
+        if let Some(qubits) = object.optional("1Q") {
+            for (key, qubit) in qubits.object()?.entries() {
+                isa.qubits.insert(key.clone(), Qubit::read(qubit)?);
+            }
+        }
+        if let Some(edges) = object.optional("2Q") {
+            for (key, edge) in edges.object()?.entries() {
+                isa.edges.insert(key.clone(), Edge::read(edge)?);
+            }
+        }
+        Ok(isa)
+    }
+
+    fn to_value(&self) -> Value {
+        let qubits = self.qubits.iter().map(|(key, qubit)| {
+            (
+                key.clone(),
+                site_value(json!({"id": qubit.id}), qubit.dead, &qubit.gates),
+            )
+        });
+        let edges = self.edges.iter().map(|(key, edge)| {
+            (
+                key.clone(),
+                site_value(json!({"ids": edge.ids}), edge.dead, &edge.gates),
+            )
+        });
+        json!({
+            "1Q": Value::Object(qubits.collect()),
+            "2Q": Value::Object(edges.collect()),
+        })
+    }
+}
+
+impl Qubit {
+    fn read(node: Node) -> Result<Self, IsaError> {
+        let object = node.object()?;
+        Ok(Self {
+            id: object.required("id")?.u32()?,
+            dead: read_dead(&object)?,
+            gates: read_gates(&object)?,
+        })
+    }
+}
+
+impl Edge {
+    fn read(node: Node) -> Result<Self, IsaError> {
+        let object = node.object()?;
+        let ids = object.required("ids")?;
+        let [a, b] = ids.array(|id| id.u32())?[..] else {
+            return Err(ids.error(Reason::InvalidValue("expected 2 qubit ids".to_string())));
+        };
+        Ok(Self {
+            ids: [a, b],
+            dead: read_dead(&object)?,
+            gates: read_gates(&object)?,
+        })
+    }
+}
+
+fn read_dead(object: &Object) -> Result<bool, IsaError> {
+    Ok(object.read("dead", |node| node.bool())?.unwrap_or_default())
+}
+
+fn read_gates(object: &Object) -> Result<Vec<Operation>, IsaError> {
+    Ok(object
+        .read("gates", |node| node.array(Operation::read))?
+        .unwrap_or_default())
+}
+
+/// A qubit or edge entry: `site` (holding its id or ids) with its liveness and gates
+fn site_value(mut site: Value, dead: bool, gates: &[Operation]) -> Value {
+    if dead {
+        site["dead"] = true.into();
+    }
+    site["gates"] = gates.iter().map(Operation::to_value).collect();
+    site
+}
+
+impl Operation {
+    fn read(node: Node) -> Result<Self, IsaError> {
+        let object = node.object()?;
+        let operator_type = object.required("operator_type")?;
+        match operator_type.string()?.as_str() {
+            "gate" => Ok(Operation::Gate(GateInfo {
+                operator: object.required("operator")?.string()?,
+                parameters: object
+                    .read("parameters", |node| node.array(Parameter::read))?
+                    .unwrap_or_default(),
+                arguments: object
+                    .read("arguments", |node| node.array(Argument::read))?
+                    .unwrap_or_default(),
+                duration: object.read("duration", |node| node.f64())?,
+                fidelity: object.read("fidelity", |node| node.f64())?,
+            })),
+            "measure" => Ok(Operation::Measure(MeasureInfo {
+                operator: object.required("operator")?.string()?,
+                qubit: Argument::read(object.required("qubit")?)?,
+                target: object.read("target", |node| node.string())?,
+                duration: object.read("duration", |node| node.f64())?,
+                fidelity: object.read("fidelity", |node| node.f64())?,
+            })),
+            other => Err(operator_type.error(Reason::InvalidValue(format!(
+                "expected `gate` or `measure`, found `{other}`"
+            )))),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        let (mut object, duration, fidelity) = match self {
+            Operation::Gate(gate) => (
+                json!({
+                    "operator_type": "gate",
+                    "operator": gate.operator,
+                    "parameters": gate.parameters.iter().map(Parameter::to_value).collect::<Value>(),
+                    "arguments": gate.arguments.iter().map(Argument::to_value).collect::<Value>(),
+                }),
+                gate.duration,
+                gate.fidelity,
+            ),
+            Operation::Measure(measure) => (
+                json!({
+                    "operator_type": "measure",
+                    "operator": measure.operator,
+                    "qubit": measure.qubit.to_value(),
+                    "target": measure.target,
+                }),
+                measure.duration,
+                measure.fidelity,
+            ),
+        };
+        if let Some(duration) = duration {
+            object["duration"] = duration.into();
+        }
+        if let Some(fidelity) = fidelity {
+            object["fidelity"] = fidelity.into();
+        }
+        object
+    }
+}
+
+impl Parameter {
+    fn read(node: Node) -> Result<Self, IsaError> {
+        match node.value() {
+            Value::String(name) => Ok(Parameter::Name(name.clone())),
+            Value::Number(_) => node.f64().map(Parameter::Value),
+            _ => Err(node.wrong_type("a number or a string")),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        match self {
+            Parameter::Value(value) => (*value).into(),
+            Parameter::Name(name) => name.clone().into(),
+        }
+    }
+}
+
+impl Argument {
+    fn read(node: Node) -> Result<Self, IsaError> {
+        match node.value() {
+            Value::String(name) => Ok(Argument::Name(name.clone())),
+            Value::Number(_) => node.u32().map(Argument::Qubit),
+            _ => Err(node.wrong_type("a qubit index or a string")),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        match self {
+            Argument::Qubit(id) => (*id).into(),
+            Argument::Name(name) => name.clone().into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quilc::{fixtures::aspen_9_device, Error};
+
+    #[test]
+    fn test_restrict_to() {
+        let isa = aspen_9_device().isa;
+        let restricted = isa.restrict_to(&[0, 1, 7]).unwrap();
+
+        assert!(!restricted.qubit(0).unwrap().dead);
+        assert!(!restricted.qubit(7).unwrap().dead);
+        assert!(restricted.qubit(2).unwrap().dead);
+        assert!(!restricted.edge(0, 1).unwrap().dead);
+        assert!(!restricted.edge(0, 7).unwrap().dead);
+        assert!(restricted.edge(1, 16).unwrap().dead);
+        // The original is left untouched
+        assert!(!isa.qubit(2).unwrap().dead);
+
+        assert!(matches!(
+            isa.restrict_to(&[0, 8]),
+            Err(Error::UnknownQubit(8))
+        ));
+    }
+
+    #[test]
+    fn test_restriction_keeps_specs() {
+        let mut device = aspen_9_device();
+        device.device_type = Some("TargetDevice".to_string());
+        device.specs = serde_json::json!({
+            "1Q": {"0": {"f1QRB": 0.99}, "2": {"f1QRB": 0.98}},
+            "2Q": {"0-1": {"fCZ": 0.9}, "0-7": {"fCZ": 0.95}},
+        })
+        .as_object()
+        .unwrap()
+        .clone();
+
+        let restricted = device.restrict_to(&[0, 1]).unwrap();
+        assert_eq!(restricted.device_type.as_deref(), Some("TargetDevice"));
+        assert_eq!(
+            serde_json::Value::Object(restricted.specs),
+            serde_json::json!({
+                "1Q": {"0": {"f1QRB": 0.99}},
+                "2Q": {"0-1": {"fCZ": 0.9}},
+            })
+        );
+
+        let masked = device.without_edges(&[(0, 7)]).unwrap();
+        assert_eq!(masked.specs["2Q"], serde_json::json!({"0-1": {"fCZ": 0.9}}));
+        assert_eq!(masked.specs["1Q"], device.specs["1Q"]);
+    }
+
+    #[test]
+    fn test_without_edges() {
+        let isa = aspen_9_device().isa;
+        let masked = isa.without_edges(&[(7, 0)]).unwrap();
+        assert!(masked.edge(0, 7).unwrap().dead);
+        assert!(!masked.qubit(0).unwrap().dead);
+        assert!(!isa.edge(0, 7).unwrap().dead);
+
+        assert!(matches!(
+            isa.without_edges(&[(0, 2)]),
+            Err(Error::UnknownEdge(0, 2))
+        ));
+    }
+
+    #[test]
+    fn test_gate_lookup() {
+        let isa = aspen_9_device().isa;
+        let half_pi = std::f64::consts::FRAC_PI_2;
+
+        let rx = isa.gate("RX", &[Some(-3.0 * half_pi)], &[0]).unwrap();
+        assert_eq!(rx.parameters, vec![Parameter::Value(half_pi)]);
+        assert!(isa.gate("RX", &[Some(0.3)], &[0]).is_none());
+        assert!(isa.gate("RX", &[None], &[0]).is_none());
+        assert!(isa.gate("RZ", &[None], &[0]).is_some());
+        assert_eq!(
+            isa.gate("XY", &[Some(0.3)], &[1, 0]).unwrap().duration,
+            Some(200.0)
+        );
+        assert!(isa.gate("XY", &[Some(0.3)], &[0, 2]).is_none());
+        assert_eq!(isa.measure(0, true).unwrap().fidelity, Some(0.969));
+    }
+
+    #[test]
+    fn test_aspen_9_round_trip() {
+        let device = aspen_9_device();
+        assert_eq!(device.isa.qubits.len(), 32);
+        assert_eq!(device.isa.edges.len(), 38);
+        assert!(device.isa.edge(2, 1).unwrap().dead);
+        assert!(!device.isa.edge(0, 1).unwrap().dead);
+
+        let reparsed: TargetDevice = device.to_json().parse().unwrap();
+        assert_eq!(reparsed, device);
+    }
+
+    #[test]
+    fn test_read_errors_name_the_entry() {
+        let error =
+            r#"{"isa": {"1Q": {"0": {"id": "zero"}}}}"#.parse::<TargetDevice>().unwrap_err();
+        assert_eq!(error.pointer, "/isa/1Q/0/id");
+        assert_eq!(
+            error.reason,
+            Reason::WrongType {
+                expected: "a qubit index",
+                found: "a string"
+            }
+        );
+
+        let error = r#"{"isa": {"1Q": {"0": {"id": 0, "gates": [{"operator_type": "pulse"}]}}}}"#
+            .parse::<TargetDevice>()
+            .unwrap_err();
+        assert_eq!(error.pointer, "/isa/1Q/0/gates/0/operator_type");
+    }
+}
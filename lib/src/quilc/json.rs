@@ -0,0 +1,155 @@
+//! Reading the JSON documents of [`isa`](super::isa) and [`qcs`](super::qcs) from
+//! [`serde_json::Value`]s, so that the typed documents don't depend on serde's derive
+//! macros and `serde` can stay an optional dependency.
+
+use serde_json::{Map, Value};
+
+use super::validation::{join, type_name, IsaError, Reason};
+
+/// Parse JSON text into a value, reporting a syntax error against the whole document
+pub(crate) fn parse(json: &str) -> Result<Value, IsaError> {
+    serde_json::from_str(json).map_err(|e| IsaError {
+        pointer: String::new(),
+        reason: Reason::InvalidValue(format!("invalid JSON: {e}")),
+    })
+}
+
+/// A value in a document, together with the JSON pointer it was found at
+pub(crate) struct Node<'v> {
+    value: &'v Value,
+    pointer: String,
+}
+
+impl<'v> Node<'v> {
+    /// The root of a document
+    pub(crate) fn root(value: &'v Value) -> Self {
+        Self {
+            value,
+            pointer: String::new(),
+        }
+    }
+
+    pub(crate) fn value(&self) -> &'v Value {
+        self.value
+    }
+
+    pub(crate) fn error(&self, reason: Reason) -> IsaError {
+        IsaError {
+            pointer: self.pointer.clone(),
+            reason,
+        }
+    }
+
+    pub(crate) fn wrong_type(&self, expected: &'static str) -> IsaError {
+        self.error(Reason::WrongType {
+            expected,
+            found: type_name(self.value),
+        })
+    }
+
+    pub(crate) fn object(&self) -> Result<Object<'v>, IsaError> {
+        match self.value {
+            Value::Object(fields) => Ok(Object {
+                fields,
+                pointer: self.pointer.clone(),
+            }),
+            _ => Err(self.wrong_type("an object")),
+        }
+    }
+
+    pub(crate) fn array<T>(
+        &self,
+        mut item: impl FnMut(Node<'v>) -> Result<T, IsaError>,
+    ) -> Result<Vec<T>, IsaError> {
+        match self.value {
+            Value::Array(items) => items
+                .iter()
+                .enumerate()
+                .map(|(i, value)| {
+                    item(Node {
+                        value,
+                        pointer: join(&self.pointer, i),
+                    })
+                })
+                .collect(),
+            _ => Err(self.wrong_type("an array")),
+        }
+    }
+
+    pub(crate) fn string(&self) -> Result<String, IsaError> {
+        self.value
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| self.wrong_type("a string"))
+    }
+
+    pub(crate) fn f64(&self) -> Result<f64, IsaError> {
+        self.value
+            .as_f64()
+            .ok_or_else(|| self.wrong_type("a number"))
+    }
+
+    pub(crate) fn u32(&self) -> Result<u32, IsaError> {
+        self.value
+            .as_u64()
+            .and_then(|n| u32::try_from(n).ok())
+            .ok_or_else(|| self.wrong_type("a qubit index"))
+    }
+
+    pub(crate) fn bool(&self) -> Result<bool, IsaError> {
+        self.value
+            .as_bool()
+            .ok_or_else(|| self.wrong_type("a boolean"))
+    }
+}
+
+/// The fields of an object in a document
+pub(crate) struct Object<'v> {
+    fields: &'v Map<String, Value>,
+    pointer: String,
+}
+
+impl<'v> Object<'v> {
+    pub(crate) fn fields(&self) -> &'v Map<String, Value> {
+        self.fields
+    }
+
+    pub(crate) fn required(&self, key: &'static str) -> Result<Node<'v>, IsaError> {
+        self.optional(key).ok_or_else(|| IsaError {
+            pointer: self.pointer.clone(),
+            reason: Reason::MissingKey(key),
+        })
+    }
+
+    /// The field with the given key, treating `null` as absent
+    pub(crate) fn optional(&self, key: &str) -> Option<Node<'v>> {
+        self.fields
+            .get(key)
+            .filter(|value| !value.is_null())
+            .map(|value| Node {
+                value,
+                pointer: join(&self.pointer, key),
+            })
+    }
+
+    /// Read an optional field, giving `None` when it is absent or `null`
+    pub(crate) fn read<T>(
+        &self,
+        key: &str,
+        read: impl FnOnce(Node<'v>) -> Result<T, IsaError>,
+    ) -> Result<Option<T>, IsaError> {
+        self.optional(key).map(read).transpose()
+    }
+
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (&'v String, Node<'v>)> + '_ {
+        self.fields.iter().map(|(key, value)| {
+            (
+                key,
+                Node {
+                    value,
+                    pointer: join(&self.pointer, key),
+                },
+            )
+        })
+    }
+}
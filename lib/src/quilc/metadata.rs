@@ -0,0 +1,221 @@
+//! [`CompilationMetadata`] for programs compiled without protoquil, where libquil does
+//! not report any. It is derived from the compiled program and the chip's ISA.
+
+use std::collections::HashMap;
+
+use quil_rs::instruction::Instruction;
+
+use super::{
+    isa::{real_parameters, Isa},
+    placement::fixed_indices,
+    CompilationMetadata,
+};
+
+/// Parse the `"#(0 1 2)"` data of a `PRAGMA CURRENT_REWIRING` or `PRAGMA EXPECTED_REWIRING`
+pub(crate) fn parse_rewiring(data: &str) -> Option<Vec<u32>> {
+    data.trim()
+        .strip_prefix("#(")?
+        .strip_suffix(')')?
+        .split_whitespace()
+        .map(|index| index.parse().ok())
+        .collect()
+}
+
+/// Format a rewiring as the data of a `PRAGMA CURRENT_REWIRING`
+pub(crate) fn format_rewiring(rewiring: &[u32]) -> String {
+    let indices = rewiring.iter().map(u32::to_string).collect::<Vec<_>>();
+    format!("#({})", indices.join(" "))
+}
+
+/// Every rewiring quilc recorded in a compiled program, in program order. The first is
+/// the initial placement and the last the final rewiring.
+pub(crate) fn rewirings(program: &quil_rs::Program) -> Vec<Vec<u32>> {
+    program
+        .body_instructions()
+        .filter_map(|instruction| match instruction {
+            Instruction::Pragma(pragma)
+                if pragma.name == "CURRENT_REWIRING" || pragma.name == "EXPECTED_REWIRING" =>
+            {
+                pragma.data.as_deref().and_then(parse_rewiring)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Tracks, per qubit, the quantities needed for depths and duration along the
+/// longest dependency chain ending on that qubit
+#[derive(Default)]
+struct Frontier {
+    depth: HashMap<u32, u32>,
+    multiqubit_depth: HashMap<u32, u32>,
+    time: HashMap<u32, f64>,
+}
+
+impl Frontier {
+    fn advance(&mut self, qubits: &[u32], gate: bool, duration: f64) {
+        let max_of = |map: &HashMap<u32, u32>| {
+            qubits
+                .iter()
+                .filter_map(|q| map.get(q))
+                .max()
+                .copied()
+                .unwrap_or(0)
+        };
+        let depth = max_of(&self.depth) + u32::from(gate);
+        let multiqubit_depth = max_of(&self.multiqubit_depth) + u32::from(gate && qubits.len() > 1);
+        let end = qubits
+            .iter()
+            .filter_map(|q| self.time.get(q))
+            .fold(0.0, |a: f64, &b| a.max(b))
+            + duration;
+        for &qubit in qubits {
+            self.depth.insert(qubit, depth);
+            self.multiqubit_depth.insert(qubit, multiqubit_depth);
+            self.time.insert(qubit, end);
+        }
+    }
+
+    /// Synchronise the given qubits, or every qubit seen so far if `qubits` is empty
+    fn fence(&mut self, qubits: &[u32]) {
+        let qubits = if qubits.is_empty() {
+            self.time.keys().copied().collect()
+        } else {
+            qubits.to_vec()
+        };
+        self.advance(&qubits, false, 0.0);
+    }
+}
+
+/// Derive the metadata libquil reports for protoquil compiles from a compiled program.
+///
+/// The final rewiring comes from the last rewiring pragma quilc emits. Depths and gate
+/// volume are computed over the program's gate dependencies. Duration and fidelity need
+/// a native operation in `isa` for every gate (and, for duration, measurement). Programs
+/// with control flow, and quantities quilc tracks internally (topological swaps, runtime
+/// estimation), are reported as `None`.
+pub(crate) fn estimate(program: &quil_rs::Program, isa: Option<&Isa>) -> CompilationMetadata {
+    let mut final_rewiring = vec![];
+    let mut frontier = Frontier::default();
+    let mut gate_volume = 0;
+    let mut schedulable = true;
+    let mut duration_known = isa.is_some();
+    let mut fidelity = isa.map(|_| 1.0);
+
+    for instruction in program.body_instructions() {
+        match instruction {
+            Instruction::Pragma(pragma)
+                if pragma.name == "CURRENT_REWIRING" || pragma.name == "EXPECTED_REWIRING" =>
+            {
+                if let Some(rewiring) = pragma.data.as_deref().and_then(parse_rewiring) {
+                    final_rewiring = rewiring;
+                }
+            }
+            Instruction::Gate(gate) => {
+                let Some(qubits) = fixed_indices(&gate.qubits) else {
+                    schedulable = false;
+                    continue;
+                };
+                let parameters = real_parameters(&gate.parameters);
+                let native = isa.and_then(|isa| isa.gate(&gate.name, &parameters, &qubits));
+                let duration = native.and_then(|native| native.duration);
+                duration_known &= duration.is_some();
+                fidelity = fidelity
+                    .zip(native.and_then(|native| native.fidelity))
+                    .map(|(total, fidelity)| total * fidelity);
+                gate_volume += 1;
+                frontier.advance(&qubits, true, duration.unwrap_or(0.0));
+            }
+            Instruction::Measurement(measurement) => {
+                let Some(qubits) = fixed_indices(std::slice::from_ref(&measurement.qubit)) else {
+                    schedulable = false;
+                    continue;
+                };
+                let duration = isa
+                    .and_then(|isa| isa.measure(qubits[0], measurement.target.is_some()))
+                    .and_then(|native| native.duration);
+                duration_known &= duration.is_some();
+                frontier.advance(&qubits, false, duration.unwrap_or(0.0));
+            }
+            Instruction::Fence(fence) => match fixed_indices(&fence.qubits) {
+                Some(qubits) => frontier.fence(&qubits),
+                None => schedulable = false,
+            },
+            Instruction::Reset(_) | Instruction::Delay(_) => duration_known = false,
+            Instruction::Label(_)
+            | Instruction::Jump(_)
+            | Instruction::JumpWhen(_)
+            | Instruction::JumpUnless(_)
+            | Instruction::Halt => schedulable = false,
+            _ => {}
+        }
+    }
+
+    let max_of = |map: &HashMap<u32, u32>| map.values().max().copied().unwrap_or(0);
+    let duration = frontier.time.values().fold(0.0, |a: f64, &b| a.max(b));
+    CompilationMetadata {
+        final_rewiring,
+        gate_depth: Some(max_of(&frontier.depth)).filter(|_| schedulable),
+        multiqubit_gate_depth: Some(max_of(&frontier.multiqubit_depth)).filter(|_| schedulable),
+        gate_volume: Some(gate_volume).filter(|_| schedulable),
+        topological_swaps: None,
+        program_duration: Some(duration).filter(|_| schedulable && duration_known),
+        program_fidelity: fidelity.filter(|_| schedulable),
+        qpu_runtime_estimation: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quilc::fixtures::aspen_9_device;
+
+    #[test]
+    fn test_estimate() {
+        let program: quil_rs::Program = r##"
+PRAGMA EXPECTED_REWIRING "#(1 0 2)"
+DECLARE ro BIT[2]
+RX(pi/2) 0
+RZ(0.3) 0
+XY(pi) 0 1
+RX(pi/2) 2
+MEASURE 0 ro[0]
+PRAGMA CURRENT_REWIRING "#(0 1 2)"
+"##
+        .parse()
+        .unwrap();
+        let isa = aspen_9_device().isa;
+        let metadata = estimate(&program, Some(&isa));
+
+        assert_eq!(metadata.final_rewiring, vec![0, 1, 2]);
+        assert_eq!(metadata.gate_depth, Some(3));
+        assert_eq!(metadata.multiqubit_gate_depth, Some(1));
+        assert_eq!(metadata.gate_volume, Some(4));
+        assert_eq!(metadata.topological_swaps, None);
+        assert!((metadata.program_duration.unwrap() - 2250.01).abs() < 1e-9);
+        let rx = isa.gate("RX", &[Some(std::f64::consts::FRAC_PI_2)], &[0]);
+        let xy = isa.gate("XY", &[None], &[0, 1]);
+        let rx2 = isa.gate("RX", &[Some(std::f64::consts::FRAC_PI_2)], &[2]);
+        let expected = [rx, xy, rx2]
+            .iter()
+            .map(|gate| gate.unwrap().fidelity.unwrap())
+            .product::<f64>();
+        assert!((metadata.program_fidelity.unwrap() - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_estimate_without_isa_or_with_control_flow() {
+        let program: quil_rs::Program = "H 0\nCNOT 0 1".parse().unwrap();
+        let metadata = estimate(&program, None);
+        assert_eq!(metadata.gate_depth, Some(2));
+        assert_eq!(metadata.program_duration, None);
+        assert_eq!(metadata.program_fidelity, None);
+        assert!(metadata.final_rewiring.is_empty());
+
+        let program: quil_rs::Program = "LABEL @a\nX 0\nJUMP @a".parse().unwrap();
+        let metadata = estimate(&program, Some(&aspen_9_device().isa));
+        assert_eq!(metadata.gate_depth, None);
+        assert_eq!(metadata.gate_volume, None);
+        assert_eq!(metadata.program_fidelity, None);
+    }
+}
@@ -0,0 +1,137 @@
+//! Shortening programs that are already native and placed, without re-addressing them
+
+use quil_rs::instruction::Instruction;
+
+use super::{
+    compile_program, metadata, placement::check_unmoved, Chip, CompilationMetadata, Error,
+    Placement, Program, RewiringStrategy,
+};
+
+/// Gate counts and depths of a program
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct GateStats {
+    pub gate_count: usize,
+    pub multiqubit_gate_count: usize,
+    /// `None` for programs with control flow
+    pub gate_depth: Option<u32>,
+    /// `None` for programs with control flow
+    pub multiqubit_gate_depth: Option<u32>,
+}
+
+impl GateStats {
+    pub(crate) fn of(program: &quil_rs::Program) -> Self {
+        let gates = program
+            .body_instructions()
+            .filter_map(|instruction| match instruction {
+                Instruction::Gate(gate) => Some(gate),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let CompilationMetadata {
+            gate_depth,
+            multiqubit_gate_depth,
+            ..
+        } = metadata::estimate(program, None);
+        Self {
+            gate_count: gates.len(),
+            multiqubit_gate_count: gates.iter().filter(|gate| gate.qubits.len() > 1).count(),
+            gate_depth,
+            multiqubit_gate_depth,
+        }
+    }
+}
+
+/// The output of [`optimize`]
+#[derive(Debug)]
+pub struct Optimized {
+    pub program: Program,
+    pub before: GateStats,
+    pub after: GateStats,
+}
+
+/// Shorten a program that is already placed on the chip, keeping every qubit index fixed.
+///
+/// libquil has no entry point for quilc's compressor alone, so the program is compiled
+/// with the `NAIVE` rewiring strategy, which makes addressing a no-op for a program
+/// whose 2Q gates all lie on the chip's edges; what remains is nativization and
+/// compression. If quilc nonetheless placed a qubit elsewhere or moved one while
+/// routing, such as for a 2Q gate off the chip's edges, this fails with
+/// [`Error::PlacementMismatch`] rather than return a relabelled program.
+pub fn optimize(program: &Program, chip: &Chip) -> Result<Optimized, Error> {
+    let before = GateStats::of(&program.to_quil_rs()?);
+
+    let placement = Placement::from(RewiringStrategy::Naive);
+    let result = compile_program(&placement.apply(program, chip)?, chip)?;
+    placement.check(&result)?;
+    check_unmoved(&result)?;
+
+    // Drop the rewiring pragmas quilc adds, which are meaningless for a fixed placement
+    let mut compiled = result.program.to_quil_rs()?;
+    let instructions = compiled
+        .body_instructions()
+        .filter(|instruction| {
+            !matches!(instruction, Instruction::Pragma(pragma)
+                if pragma.name.ends_with("_REWIRING"))
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    compiled = compiled.clone_without_body_instructions();
+    compiled.add_instructions(instructions);
+
+    Ok(Optimized {
+        after: GateStats::of(&compiled),
+        program: Program::from_quil_rs(&compiled)?,
+        before,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quilc::fixtures::aspen_9;
+    use crate::quilc::placement::fixed_qubits;
+
+    #[test]
+    fn test_gate_stats() {
+        let program: quil_rs::Program = "RX(pi/2) 0\nCZ 0 1\nRZ(0.1) 1\nCZ 1 2\nMEASURE 2"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            GateStats::of(&program),
+            GateStats {
+                gate_count: 4,
+                multiqubit_gate_count: 2,
+                gate_depth: Some(4),
+                multiqubit_gate_depth: Some(2),
+            }
+        );
+    }
+
+    #[test]
+    fn test_optimize_keeps_qubits() {
+        let chip = aspen_9();
+        let program: Program = "RZ(0.1) 10\nRZ(0.2) 10\nRX(pi/2) 10\nRX(-pi/2) 10\nCZ 10 11"
+            .parse()
+            .unwrap();
+
+        let optimized = optimize(&program, &chip).unwrap();
+        assert_eq!(optimized.before.gate_count, 5);
+        assert!(optimized.after.gate_count < optimized.before.gate_count);
+        assert_eq!(
+            fixed_qubits(&optimized.program.to_quil_rs().unwrap()),
+            vec![10, 11]
+        );
+    }
+
+    #[test]
+    fn test_optimize_rejects_routing() {
+        let chip = aspen_9();
+        // Qubits 0 and 2 share no edge, so quilc has to move one of them
+        let program: Program = "CZ 0 2".parse().unwrap();
+        assert!(matches!(
+            optimize(&program, &chip),
+            Err(Error::PlacementMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,319 @@
+//! Binding values to the parameters of a compiled program without compiling it again
+
+use std::collections::{HashMap, HashSet};
+
+use quil_rs::{
+    expression::{Expression, FunctionCallExpression, InfixExpression, PrefixExpression},
+    instruction::Instruction,
+};
+
+use super::{Declaration, MemoryType, Program, ProgramSummary};
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum Error {
+    #[error("{0} is not a parameter of the program")]
+    UnknownParameter(String),
+    #[error("no value given for parameter {0}")]
+    MissingParameter(String),
+    #[error("parameter {name} is declared {declared:?} but was given {given:?} values")]
+    TypeMismatch {
+        name: String,
+        declared: MemoryType,
+        given: MemoryType,
+    },
+    #[error("parameter {name} is declared with length {declared} but was given {given} values")]
+    LengthMismatch {
+        name: String,
+        declared: u64,
+        given: usize,
+    },
+}
+
+/// The values bound to one parameter, one per element of its declaration
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParameterValues {
+    Bit(Vec<bool>),
+    Octet(Vec<u8>),
+    Integer(Vec<i64>),
+    Real(Vec<f64>),
+}
+
+impl ParameterValues {
+    pub fn memory_type(&self) -> MemoryType {
+        match self {
+            Self::Bit(_) => MemoryType::Bit,
+            Self::Octet(_) => MemoryType::Octet,
+            Self::Integer(_) => MemoryType::Integer,
+            Self::Real(_) => MemoryType::Real,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Bit(values) => values.len(),
+            Self::Octet(values) => values.len(),
+            Self::Integer(values) => values.len(),
+            Self::Real(values) => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn to_reals(&self) -> Vec<f64> {
+        match self {
+            Self::Bit(values) => values.iter().map(|&v| f64::from(u8::from(v))).collect(),
+            Self::Octet(values) => values.iter().map(|&v| f64::from(v)).collect(),
+            Self::Integer(values) => values.iter().map(|&v| v as f64).collect(),
+            Self::Real(values) => values.clone(),
+        }
+    }
+}
+
+impl From<Vec<f64>> for ParameterValues {
+    fn from(values: Vec<f64>) -> Self {
+        Self::Real(values)
+    }
+}
+
+/// Replace each reference to a bound memory element in `expression` with its value
+fn substitute(expression: &Expression, values: &HashMap<&str, Vec<f64>>) -> Expression {
+    match expression {
+        Expression::Address(reference) => values
+            .get(reference.name.as_str())
+            .and_then(|values| values.get(reference.index as usize))
+            .map_or_else(
+                || expression.clone(),
+                |&value| Expression::Number(value.into()),
+            ),
+        Expression::FunctionCall(call) => Expression::FunctionCall(FunctionCallExpression::new(
+            call.function,
+            substitute(&call.expression, values).into(),
+        )),
+        Expression::Infix(infix) => Expression::Infix(InfixExpression::new(
+            substitute(&infix.left, values).into(),
+            infix.operator,
+            substitute(&infix.right, values).into(),
+        )),
+        Expression::Prefix(prefix) => Expression::Prefix(PrefixExpression::new(
+            prefix.operator,
+            substitute(&prefix.expression, values).into(),
+        )),
+        Expression::Number(_) | Expression::PiConstant | Expression::Variable(_) => {
+            expression.clone()
+        }
+    }
+}
+
+/// Collect the names of the memory regions `expression` reads
+fn referenced_regions(expression: &Expression, regions: &mut HashSet<String>) {
+    match expression {
+        Expression::Address(reference) => {
+            regions.insert(reference.name.clone());
+        }
+        Expression::FunctionCall(call) => referenced_regions(&call.expression, regions),
+        Expression::Infix(infix) => {
+            referenced_regions(&infix.left, regions);
+            referenced_regions(&infix.right, regions);
+        }
+        Expression::Prefix(prefix) => referenced_regions(&prefix.expression, regions),
+        Expression::Number(_) | Expression::PiConstant | Expression::Variable(_) => {}
+    }
+}
+
+/// A compiled program whose parameters can be bound to values repeatedly, without
+/// compiling again.
+///
+/// The parameters are the declared memory regions the program's expressions read, such
+/// as `theta` in `RX(theta[0]) 0`. Binding replaces each reference to a parameter in an
+/// expression with its value, then simplifies the expression. Declarations are kept, so
+/// the bound program still runs with the same memory layout; classical instructions that
+/// read a parameter, such as `MOVE`, are left as they are.
+#[derive(Clone, Debug)]
+pub struct ParametricProgram {
+    program: quil_rs::Program,
+    parameters: Vec<Declaration>,
+}
+
+impl ParametricProgram {
+    pub fn new(program: &Program) -> Result<Self, super::Error> {
+        Ok(Self::from_quil_rs(program.to_quil_rs()?))
+    }
+
+    pub(crate) fn from_quil_rs(program: quil_rs::Program) -> Self {
+        let mut referenced = HashSet::new();
+        for instruction in program.body_instructions() {
+            instruction
+                .clone()
+                .apply_to_expressions(|expression| referenced_regions(expression, &mut referenced));
+        }
+        let parameters = ProgramSummary::of(&program)
+            .declarations
+            .into_iter()
+            .filter(|declaration| referenced.contains(&declaration.name))
+            .collect();
+        Self {
+            program,
+            parameters,
+        }
+    }
+
+    /// The declarations of the program's parameters, in the order they are declared
+    pub fn parameters(&self) -> &[Declaration] {
+        &self.parameters
+    }
+
+    /// Check `values` against the parameters' declarations: every parameter needs a value
+    /// of its declared type for each element, and no other names may be given
+    fn check(&self, values: &HashMap<String, ParameterValues>) -> Result<(), Error> {
+        if let Some(name) = values
+            .keys()
+            .find(|name| !self.parameters.iter().any(|p| &p.name == *name))
+        {
+            return Err(Error::UnknownParameter(name.clone()));
+        }
+        for parameter in &self.parameters {
+            let given = values
+                .get(&parameter.name)
+                .ok_or_else(|| Error::MissingParameter(parameter.name.clone()))?;
+            if given.memory_type() != parameter.memory_type {
+                return Err(Error::TypeMismatch {
+                    name: parameter.name.clone(),
+                    declared: parameter.memory_type,
+                    given: given.memory_type(),
+                });
+            }
+            if given.len() as u64 != parameter.length {
+                return Err(Error::LengthMismatch {
+                    name: parameter.name.clone(),
+                    declared: parameter.length,
+                    given: given.len(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn bind_quil_rs(
+        &self,
+        values: &HashMap<String, ParameterValues>,
+    ) -> Result<quil_rs::Program, Error> {
+        self.check(values)?;
+        let reals = values
+            .iter()
+            .map(|(name, values)| (name.as_str(), values.to_reals()))
+            .collect::<HashMap<_, _>>();
+
+        let instructions = self
+            .program
+            .body_instructions()
+            .map(|instruction| {
+                let mut instruction: Instruction = instruction.clone();
+                instruction.apply_to_expressions(|expression| {
+                    *expression = substitute(expression, &reals).into_simplified();
+                });
+                instruction
+            })
+            .collect::<Vec<_>>();
+        let mut bound = self.program.clone_without_body_instructions();
+        bound.add_instructions(instructions);
+        Ok(bound)
+    }
+
+    /// A copy of the program with `values` substituted for its parameters. Fails with
+    /// [`Error::Parameter`](super::Error::Parameter) if a parameter is missing, an unknown
+    /// name is given, or values do not match a parameter's declared type and length.
+    pub fn bind(&self, values: &HashMap<String, ParameterValues>) -> Result<Program, super::Error> {
+        Program::from_quil_rs(&self.bind_quil_rs(values)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quil_rs::quil::Quil;
+
+    fn parametric() -> ParametricProgram {
+        ParametricProgram::from_quil_rs(
+            "DECLARE ro BIT[2]
+DECLARE theta REAL[2]
+DECLARE unused REAL
+RX(theta[0]) 0
+RZ(2*theta[1] + pi) 1
+CZ 0 1
+MEASURE 0 ro[0]"
+                .parse()
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_parameters() {
+        let parameters = parametric()
+            .parameters()
+            .iter()
+            .map(|p| (p.name.clone(), p.memory_type, p.length))
+            .collect::<Vec<_>>();
+        assert_eq!(parameters, vec![("theta".to_string(), MemoryType::Real, 2)]);
+    }
+
+    #[test]
+    fn test_bind() {
+        let parametric = parametric();
+        let values = HashMap::from([("theta".to_string(), vec![0.5, 0.25].into())]);
+        let bound = parametric.bind_quil_rs(&values).unwrap();
+        let gates = bound
+            .body_instructions()
+            .filter(|instruction| matches!(instruction, Instruction::Gate(_)))
+            .map(|instruction| instruction.to_quil().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            gates,
+            vec![
+                "RX(0.5) 0".to_string(),
+                format!("RZ({}) 1", 0.5 + std::f64::consts::PI),
+                "CZ 0 1".to_string(),
+            ]
+        );
+        // The template is untouched and can be bound again
+        assert!(parametric
+            .bind_quil_rs(&HashMap::from([(
+                "theta".to_string(),
+                vec![1.0, 2.0].into()
+            )]))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_bind_rejects_mismatches() {
+        let parametric = parametric();
+        let bind = |name: &str, values: ParameterValues| {
+            parametric.bind_quil_rs(&HashMap::from([(name.to_string(), values)]))
+        };
+        assert_eq!(
+            bind("theta", ParameterValues::Real(vec![0.5])).unwrap_err(),
+            Error::LengthMismatch {
+                name: "theta".to_string(),
+                declared: 2,
+                given: 1
+            }
+        );
+        assert_eq!(
+            bind("theta", ParameterValues::Integer(vec![1, 2])).unwrap_err(),
+            Error::TypeMismatch {
+                name: "theta".to_string(),
+                declared: MemoryType::Real,
+                given: MemoryType::Integer
+            }
+        );
+        assert_eq!(
+            bind("phi", ParameterValues::Real(vec![0.5])).unwrap_err(),
+            Error::UnknownParameter("phi".to_string())
+        );
+        assert_eq!(
+            parametric.bind_quil_rs(&HashMap::new()).unwrap_err(),
+            Error::MissingParameter("theta".to_string())
+        );
+    }
+}
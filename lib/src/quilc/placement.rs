@@ -0,0 +1,402 @@
+//! Control over where quilc initially places a program's logical qubits
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    str::FromStr,
+};
+
+use quil_rs::{
+    instruction::{Instruction, Qubit},
+    quil::Quil,
+};
+
+use super::{metadata, Chip, CompilationResult, Error, Program, RewiringStrategy};
+
+/// The initial placement of logical qubits onto a chip's physical qubits
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Placement {
+    /// Let quilc place the qubits with the given strategy
+    Strategy(RewiringStrategy),
+    /// Start each logical qubit (key) on the given physical qubit (value)
+    Explicit(BTreeMap<u32, u32>),
+}
+
+impl From<RewiringStrategy> for Placement {
+    fn from(strategy: RewiringStrategy) -> Self {
+        Placement::Strategy(strategy)
+    }
+}
+
+impl Placement {
+    /// Rewrite the program so that compiling it with [`compile_program`](super::compile_program)
+    /// or [`compile_protoquil`](super::compile_protoquil) starts from this placement.
+    ///
+    /// A strategy is applied with `PRAGMA INITIAL_REWIRING`. An explicit placement renames
+    /// each logical qubit to its physical qubit and uses the `NAIVE` strategy; pass the
+    /// compiled result to [`Placement::finish`] to index its rewirings by the original
+    /// logical qubits again. Fails with [`Error::InvalidPlacement`] if the program
+    /// already has a `PRAGMA INITIAL_REWIRING`.
+    pub fn apply(&self, program: &Program, chip: &Chip) -> Result<Program, Error> {
+        let has_initial_rewiring = program.to_quil_rs()?.body_instructions().any(
+            |instruction| matches!(instruction, Instruction::Pragma(pragma) if pragma.name == "INITIAL_REWIRING"),
+        );
+        if has_initial_rewiring {
+            return Err(Error::InvalidPlacement(
+                "the program already sets PRAGMA INITIAL_REWIRING".to_string(),
+            ));
+        }
+
+        let (text, strategy) = match self {
+            Placement::Strategy(strategy) => (program.to_string()?, *strategy),
+            Placement::Explicit(placement) => {
+                self.validate(program, chip)?;
+                (relabel(program, placement)?, RewiringStrategy::Naive)
+            }
+        };
+        Program::from_str(&format!("PRAGMA INITIAL_REWIRING \"{strategy}\"\n{text}"))
+    }
+
+    fn validate(&self, program: &Program, chip: &Chip) -> Result<(), Error> {
+        let Placement::Explicit(placement) = self else {
+            return Ok(());
+        };
+
+        let mut seen = BTreeSet::new();
+        for (&logical, &physical) in placement {
+            if !seen.insert(physical) {
+                return Err(Error::InvalidPlacement(format!(
+                    "logical qubit {logical} is placed on physical qubit {physical}, which is already used"
+                )));
+            }
+            if let Some(isa) = chip.isa() {
+                match isa.qubit(physical) {
+                    None => return Err(Error::UnknownQubit(physical)),
+                    Some(qubit) if qubit.dead => {
+                        return Err(Error::InvalidPlacement(format!(
+                            "logical qubit {logical} is placed on dead physical qubit {physical}"
+                        )))
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        if let Some(logical) = fixed_qubits(&program.to_quil_rs()?)
+            .into_iter()
+            .find(|logical| !placement.contains_key(logical))
+        {
+            return Err(Error::InvalidPlacement(format!(
+                "logical qubit {logical} is used by the program but not placed"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Finish the result of compiling a program prepared with [`Placement::apply`].
+    ///
+    /// An explicit placement relabels the program, so the rewirings quilc records, in the
+    /// result's metadata and in the compiled program's `PRAGMA CURRENT_REWIRING` and
+    /// `PRAGMA EXPECTED_REWIRING`, are indexed by the physical qubit each logical qubit
+    /// was placed on. They are rewritten here to be indexed by the original program's
+    /// logical qubits, as they are for every other placement. The result is then
+    /// [checked](Placement::check).
+    pub fn finish(&self, mut result: CompilationResult) -> Result<CompilationResult, Error> {
+        if let Placement::Explicit(placement) = self {
+            let program = result.program.to_quil_rs()?;
+            let mut instructions = program.body_instructions().cloned().collect::<Vec<_>>();
+            for instruction in &mut instructions {
+                let Instruction::Pragma(pragma) = instruction else {
+                    continue;
+                };
+                if pragma.name != "CURRENT_REWIRING" && pragma.name != "EXPECTED_REWIRING" {
+                    continue;
+                }
+                if let Some(rewiring) = pragma.data.as_deref().and_then(metadata::parse_rewiring) {
+                    let rewiring = unrelabel(placement, &rewiring)?;
+                    pragma.data = Some(metadata::format_rewiring(&rewiring));
+                }
+            }
+            let mut program = program.clone_without_body_instructions();
+            program.add_instructions(instructions);
+            result.program = Program::from_quil_rs(&program)?;
+
+            if let Some(metadata) = &mut result.metadata {
+                if !metadata.final_rewiring.is_empty() {
+                    metadata.final_rewiring = unrelabel(placement, &metadata.final_rewiring)?;
+                }
+            }
+        }
+        self.check(&result)?;
+        Ok(result)
+    }
+
+    /// Check a result [finished](Placement::finish) for this placement: every qubit of an
+    /// explicit or `NAIVE` placement must start on the physical qubit it was placed on,
+    /// according to the first rewiring quilc recorded in the compiled program. A mismatch
+    /// means quilc placed the qubit elsewhere; later moves made while routing are not a
+    /// mismatch. Other strategies choose their own placement and always pass.
+    pub fn check(&self, result: &CompilationResult) -> Result<(), Error> {
+        let program = result.program.to_quil_rs()?;
+        let expected = match self {
+            Placement::Strategy(RewiringStrategy::Naive) => fixed_qubits(&program)
+                .into_iter()
+                .map(|qubit| (qubit, qubit))
+                .collect(),
+            Placement::Explicit(placement) => placement.clone(),
+            Placement::Strategy(_) => return Ok(()),
+        };
+        let rewiring = metadata::rewirings(&program)
+            .into_iter()
+            .next()
+            .ok_or_else(|| {
+                Error::InvalidRewiring("the compiled program records no rewiring".to_string())
+            })?;
+
+        for (logical, physical) in expected {
+            let actual = rewiring.get(logical as usize).copied();
+            if actual != Some(physical) {
+                return Err(Error::PlacementMismatch {
+                    logical,
+                    expected: physical,
+                    actual,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check that quilc did not move any qubit while compiling: the last rewiring recorded in
+/// the compiled program must equal the first. Fails with [`Error::PlacementMismatch`]
+/// for the first qubit that ended elsewhere; a program that records no rewiring passes.
+pub(crate) fn check_unmoved(result: &CompilationResult) -> Result<(), Error> {
+    let rewirings = metadata::rewirings(&result.program.to_quil_rs()?);
+    let (Some(first), Some(last)) = (rewirings.first(), rewirings.last()) else {
+        return Ok(());
+    };
+    match (0..)
+        .zip(first)
+        .find(|&(logical, _)| last.get(logical as usize) != first.get(logical as usize))
+    {
+        Some((logical, &expected)) => Err(Error::PlacementMismatch {
+            logical,
+            expected,
+            actual: last.get(logical as usize).copied(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Re-index a rewiring of a program relabelled with `placement` by the original program's
+/// logical qubits. Each placed qubit was relabelled with its physical qubit; the qubits
+/// the program doesn't use take the remaining labels in ascending order, which keeps the
+/// rewiring one-to-one.
+fn unrelabel(placement: &BTreeMap<u32, u32>, rewiring: &[u32]) -> Result<Vec<u32>, Error> {
+    let len = rewiring.len() as u32;
+    if let Some((logical, physical)) = placement
+        .iter()
+        .find(|(&logical, &physical)| logical >= len || physical >= len)
+    {
+        return Err(Error::InvalidRewiring(format!(
+            "logical qubit {logical} was placed on physical qubit {physical}, outside a rewiring of {len} qubits"
+        )));
+    }
+
+    let placed = placement.values().collect::<BTreeSet<_>>();
+    let mut free_labels = (0..len).filter(|label| !placed.contains(label));
+    Ok((0..len)
+        .map(|logical| {
+            let label = match placement.get(&logical) {
+                Some(&physical) => physical,
+                // There are as many free labels as unplaced qubits
+                None => free_labels.next().unwrap_or(logical),
+            };
+            rewiring[label as usize]
+        })
+        .collect())
+}
+
+/// The index of a fixed qubit, or `None` for a placeholder or variable
+pub(crate) fn fixed_index(qubit: &Qubit) -> Option<u32> {
+    match qubit {
+        Qubit::Fixed(index) => u32::try_from(*index).ok(),
+        _ => None,
+    }
+}
+
+/// The indices of `qubits`, or `None` if any of them is not a fixed qubit
+pub(crate) fn fixed_indices(qubits: &[Qubit]) -> Option<Vec<u32>> {
+    qubits.iter().map(fixed_index).collect()
+}
+
+/// The fixed qubit indices used by a program, in ascending order
+pub(crate) fn fixed_qubits(program: &quil_rs::Program) -> Vec<u32> {
+    let mut qubits = program
+        .get_used_qubits()
+        .iter()
+        .filter_map(fixed_index)
+        .collect::<Vec<_>>();
+    qubits.sort_unstable();
+    qubits
+}
+
+/// Rename the program's fixed qubits with `map`, leaving those it returns `None` for
+pub(crate) fn relabel_qubits(
+    program: &quil_rs::Program,
+    map: impl Fn(u32) -> Option<u32>,
+) -> quil_rs::Program {
+    let mut instructions = program.body_instructions().cloned().collect::<Vec<_>>();
+    for instruction in &mut instructions {
+        for qubit in instruction.get_qubits_mut() {
+            if let Qubit::Fixed(index) = qubit {
+                if let Some(relabelled) = u32::try_from(*index).ok().and_then(&map) {
+                    *index = u64::from(relabelled);
+                }
+            }
+        }
+    }
+    let mut program = program.clone_without_body_instructions();
+    program.add_instructions(instructions);
+    program
+}
+
+/// Rewrite the program's fixed qubits according to `placement`, returning Quil text
+fn relabel(program: &Program, placement: &BTreeMap<u32, u32>) -> Result<String, Error> {
+    relabel_qubits(&program.to_quil_rs()?, |qubit| {
+        placement.get(&qubit).copied()
+    })
+    .to_quil()
+    .map_err(|e| Error::ToQuil(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quilc::compile_program;
+    use crate::quilc::fixtures::aspen_9;
+
+    #[test]
+    fn test_explicit_placement() {
+        let chip = aspen_9();
+        let program: Program = "H 0\nCNOT 0 1".parse().unwrap();
+        let placement = Placement::Explicit([(0, 10), (1, 11)].into());
+
+        let prepared = placement.apply(&program, &chip).unwrap();
+        let result = placement
+            .finish(compile_program(&prepared, &chip).unwrap())
+            .unwrap();
+        let used = fixed_qubits(&result.program.to_quil_rs().unwrap());
+        assert_eq!(used, vec![10, 11]);
+        // The metadata is indexed by the program's own qubits
+        assert_eq!(&result.metadata.unwrap().final_rewiring[..2], &[10, 11]);
+    }
+
+    #[test]
+    fn test_naive_placement() {
+        let chip = aspen_9();
+        let program: Program = "H 0\nCNOT 0 1".parse().unwrap();
+        let placement = Placement::from(RewiringStrategy::Naive);
+
+        let prepared = placement.apply(&program, &chip).unwrap();
+        let result = placement
+            .finish(compile_program(&prepared, &chip).unwrap())
+            .unwrap();
+        assert_eq!(&result.metadata.unwrap().final_rewiring[..2], &[0, 1]);
+    }
+
+    #[test]
+    fn test_unrelabel() {
+        // Logical 0 was relabelled 2, and unplaced logical 2 took the free label 0
+        let placement = [(0, 2), (1, 1)].into();
+        assert_eq!(unrelabel(&placement, &[5, 1, 3]).unwrap(), vec![3, 1, 5]);
+        assert!(matches!(
+            unrelabel(&placement, &[0, 1]),
+            Err(Error::InvalidRewiring(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_reports_mismatch() {
+        let placement = Placement::Explicit([(0, 2), (1, 1)].into());
+        // Qubit 0 starts on qubit 3; where it ends up does not matter
+        let result = CompilationResult {
+            program: "PRAGMA EXPECTED_REWIRING \"#(3 1 0)\"
+X 1
+PRAGMA CURRENT_REWIRING \"#(2 1 0)\""
+                .parse()
+                .unwrap(),
+            metadata: None,
+        };
+
+        assert!(matches!(
+            placement.check(&result),
+            Err(Error::PlacementMismatch {
+                logical: 0,
+                expected: 2,
+                actual: Some(3)
+            })
+        ));
+    }
+
+    #[test]
+    fn test_check_unmoved() {
+        let routed = CompilationResult {
+            program: "PRAGMA EXPECTED_REWIRING \"#(0 1 2)\"
+SWAP 1 2
+PRAGMA CURRENT_REWIRING \"#(0 2 1)\""
+                .parse()
+                .unwrap(),
+            metadata: None,
+        };
+        assert!(matches!(
+            check_unmoved(&routed),
+            Err(Error::PlacementMismatch {
+                logical: 1,
+                expected: 1,
+                actual: Some(2)
+            })
+        ));
+
+        let unmoved = CompilationResult {
+            program: "PRAGMA EXPECTED_REWIRING \"#(0 1 2)\"
+X 1
+PRAGMA CURRENT_REWIRING \"#(0 1 2)\""
+                .parse()
+                .unwrap(),
+            metadata: None,
+        };
+        check_unmoved(&unmoved).unwrap();
+    }
+
+    #[test]
+    fn test_existing_initial_rewiring_is_rejected() {
+        let program: Program = "PRAGMA INITIAL_REWIRING \"GREEDY\"\nCNOT 0 1"
+            .parse()
+            .unwrap();
+        assert!(matches!(
+            Placement::from(RewiringStrategy::Naive).apply(&program, &aspen_9()),
+            Err(Error::InvalidPlacement(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_explicit_placements() {
+        let chip = aspen_9();
+        let program: Program = "CNOT 0 1".parse().unwrap();
+
+        let duplicate = Placement::Explicit([(0, 10), (1, 10)].into());
+        assert!(matches!(
+            duplicate.apply(&program, &chip),
+            Err(Error::InvalidPlacement(_))
+        ));
+        let missing = Placement::Explicit([(0, 10)].into());
+        assert!(matches!(
+            missing.apply(&program, &chip),
+            Err(Error::InvalidPlacement(_))
+        ));
+        let unknown = Placement::Explicit([(0, 10), (1, 8)].into());
+        assert!(matches!(
+            unknown.apply(&program, &chip),
+            Err(Error::UnknownQubit(8))
+        ));
+    }
+}
@@ -0,0 +1,475 @@
+//! Conversion from the QCS API `InstructionSetArchitecture` document to the
+//! [`isa`](super::isa) format understood by quilc.
+//!
+//! The mapping follows the one pyQuil uses when it builds a compiler ISA from
+//! a QCS quantum processor: benchmark characteristics become gate fidelities,
+//! durations fall back to Rigetti's nominal defaults, and qubits or edges without
+//! any supported operation are marked dead.
+
+use std::{collections::BTreeMap, f64::consts::PI};
+
+use super::{
+    isa::{self, Argument, GateInfo, Isa, MeasureInfo, Operation, Parameter},
+    json as document,
+    validation::IsaError,
+};
+
+const PERFECT_FIDELITY: f64 = 1.0;
+const PERFECT_DURATION: f64 = 0.01;
+
+const DEFAULT_1Q_DURATION: f64 = 50.0;
+const DEFAULT_2Q_DURATION: f64 = 200.0;
+const DEFAULT_MEASURE_DURATION: f64 = 2000.0;
+
+const DEFAULT_RX_FIDELITY: f64 = 0.97;
+const DEFAULT_MEASURE_FIDELITY: f64 = 0.90;
+
+/// The benchmarks searched, in order, for a qubit's 1Q randomized-benchmarking fidelity
+const RB_1Q_BENCHMARKS: [&str; 2] = [
+    "randomized_benchmark_simultaneous_1q",
+    "randomized_benchmark_1q",
+];
+
+/// A QCS API instruction set architecture
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InstructionSetArchitecture {
+    pub name: String,
+    pub architecture: Architecture,
+    pub benchmarks: Vec<QcsOperation>,
+    pub instructions: Vec<QcsOperation>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Architecture {
+    pub family: Option<String>,
+    pub nodes: Vec<Node>,
+    pub edges: Vec<ArchitectureEdge>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Node {
+    pub node_id: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ArchitectureEdge {
+    pub node_ids: Vec<u32>,
+}
+
+/// An instruction or benchmark, together with the sites it is available on
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct QcsOperation {
+    pub name: String,
+    pub node_count: Option<u32>,
+    pub parameters: Vec<OperationParameter>,
+    pub sites: Vec<OperationSite>,
+    pub characteristics: Vec<Characteristic>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OperationParameter {
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OperationSite {
+    pub node_ids: Vec<u32>,
+    pub characteristics: Vec<Characteristic>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Characteristic {
+    pub name: String,
+    pub value: f64,
+    pub error: Option<f64>,
+    pub node_ids: Option<Vec<u32>>,
+    pub parameter_values: Option<Vec<f64>>,
+    pub timestamp: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid QCS ISA JSON: {0}")]
+    Json(#[from] IsaError),
+    #[error("operation {operation} references unknown node {node}")]
+    UnknownNode { operation: String, node: u32 },
+    #[error("operation {operation} references unknown edge {a}-{b}")]
+    UnknownEdge { operation: String, a: u32, b: u32 },
+    #[error(
+        "operation {operation} has a site with {count} nodes; only 1Q and 2Q sites are supported"
+    )]
+    UnsupportedSite { operation: String, count: usize },
+    #[error("architecture edge {node_ids:?} does not join exactly 2 nodes")]
+    MalformedEdge { node_ids: Vec<u32> },
+}
+
+impl std::str::FromStr for InstructionSetArchitecture {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self::read(document::Node::root(&document::parse(s)?))?)
+    }
+}
+
+// Optional fields default when they are absent or `null`, and unknown fields are ignored
+impl InstructionSetArchitecture {
+    fn read(node: document::Node) -> Result<Self, IsaError> {
+        let object = node.object()?;
+        let architecture = object.required("architecture")?.object()?;
+        Ok(Self {
+            name: object.required("name")?.string()?,
+            architecture: Architecture {
+                family: architecture.read("family", |node| node.string())?,
+                nodes: architecture.required("nodes")?.array(|node| {
+                    Ok(Node {
+                        node_id: node.object()?.required("node_id")?.u32()?,
+                    })
+                })?,
+                edges: architecture.required("edges")?.array(|edge| {
+                    Ok(ArchitectureEdge {
+                        node_ids: node_ids(edge.object()?.required("node_ids")?)?,
+                    })
+                })?,
+            },
+            benchmarks: object
+                .read("benchmarks", |node| node.array(QcsOperation::read))?
+                .unwrap_or_default(),
+            instructions: object
+                .read("instructions", |node| node.array(QcsOperation::read))?
+                .unwrap_or_default(),
+        })
+    }
+}
+
+impl QcsOperation {
+    fn read(node: document::Node) -> Result<Self, IsaError> {
+        let object = node.object()?;
+        Ok(Self {
+            name: object.required("name")?.string()?,
+            node_count: object.read("node_count", |node| node.u32())?,
+            parameters: object
+                .read("parameters", |node| {
+                    node.array(|parameter| {
+                        Ok(OperationParameter {
+                            name: parameter.object()?.required("name")?.string()?,
+                        })
+                    })
+                })?
+                .unwrap_or_default(),
+            sites: object
+                .read("sites", |node| {
+                    node.array(|site| {
+                        let site = site.object()?;
+                        Ok(OperationSite {
+                            node_ids: node_ids(site.required("node_ids")?)?,
+                            characteristics: characteristics(&site)?,
+                        })
+                    })
+                })?
+                .unwrap_or_default(),
+            characteristics: characteristics(&object)?,
+        })
+    }
+}
+
+fn node_ids(node: document::Node) -> Result<Vec<u32>, IsaError> {
+    node.array(|id| id.u32())
+}
+
+fn characteristics(object: &document::Object) -> Result<Vec<Characteristic>, IsaError> {
+    Ok(object
+        .read("characteristics", |node| {
+            node.array(|characteristic| {
+                let characteristic = characteristic.object()?;
+                Ok(Characteristic {
+                    name: characteristic.required("name")?.string()?,
+                    value: characteristic.required("value")?.f64()?,
+                    error: characteristic.read("error", |node| node.f64())?,
+                    node_ids: characteristic.read("node_ids", node_ids)?,
+                    parameter_values: characteristic
+                        .read("parameter_values", |node| node.array(|value| value.f64()))?,
+                    timestamp: characteristic.read("timestamp", |node| node.string())?,
+                })
+            })
+        })?
+        .unwrap_or_default())
+}
+
+impl InstructionSetArchitecture {
+    /// Find a characteristic of the given site, looking first at the site itself
+    /// and then at the operation-level characteristics scoped to the same nodes.
+    fn site_characteristic(
+        operation: &QcsOperation,
+        site: &OperationSite,
+        name: &str,
+    ) -> Option<f64> {
+        site.characteristics
+            .iter()
+            .chain(
+                operation
+                    .characteristics
+                    .iter()
+                    .filter(|c| c.node_ids.as_deref() == Some(site.node_ids.as_slice())),
+            )
+            .find(|c| c.name == name)
+            .map(|c| c.value)
+    }
+
+    /// The simultaneous (or, failing that, isolated) 1Q RB fidelity of a qubit
+    fn rb_1q_fidelity(&self, node: u32) -> Option<f64> {
+        RB_1Q_BENCHMARKS.iter().find_map(|benchmark| {
+            self.benchmarks
+                .iter()
+                .filter(|b| b.name == *benchmark)
+                .flat_map(|b| b.sites.iter().map(move |site| (b, site)))
+                .find(|(_, site)| site.node_ids == [node])
+                .and_then(|(b, site)| Self::site_characteristic(b, site, "fRB"))
+        })
+    }
+}
+
+fn rx_gates(node: u32, fidelity: f64) -> Vec<Operation> {
+    let mut gates = vec![Operation::Gate(GateInfo {
+        operator: "RX".to_string(),
+        parameters: vec![Parameter::Value(0.0)],
+        arguments: vec![Argument::Qubit(node)],
+        duration: Some(PERFECT_DURATION),
+        fidelity: Some(PERFECT_FIDELITY),
+    })];
+    gates.extend([PI, -PI, PI / 2.0, -PI / 2.0].into_iter().map(|angle| {
+        Operation::Gate(GateInfo {
+            operator: "RX".to_string(),
+            parameters: vec![Parameter::Value(angle)],
+            arguments: vec![Argument::Qubit(node)],
+            duration: Some(DEFAULT_1Q_DURATION),
+            fidelity: Some(fidelity),
+        })
+    }));
+    gates
+}
+
+fn rz_gate(node: u32) -> Operation {
+    Operation::Gate(GateInfo {
+        operator: "RZ".to_string(),
+        parameters: vec![Parameter::Name("_".to_string())],
+        arguments: vec![Argument::Qubit(node)],
+        duration: Some(PERFECT_DURATION),
+        fidelity: Some(PERFECT_FIDELITY),
+    })
+}
+
+fn measure_operations(node: u32, fidelity: f64) -> Vec<Operation> {
+    [Some("_".to_string()), None]
+        .into_iter()
+        .map(|target| {
+            Operation::Measure(MeasureInfo {
+                operator: "MEASURE".to_string(),
+                qubit: Argument::Qubit(node),
+                target,
+                duration: Some(DEFAULT_MEASURE_DURATION),
+                fidelity: Some(fidelity),
+            })
+        })
+        .collect()
+}
+
+/// The quilc parameters and default fidelity of each supported 2Q operation
+fn two_qubit_gate(name: &str) -> Option<(Vec<Parameter>, f64)> {
+    let theta = || vec![Parameter::Name("theta".to_string())];
+    match name {
+        "CZ" => Some((vec![], 0.89)),
+        "ISWAP" => Some((vec![], 0.90)),
+        "CPHASE" => Some((theta(), 0.85)),
+        "XY" => Some((theta(), 0.86)),
+        _ => None,
+    }
+}
+
+impl TryFrom<&InstructionSetArchitecture> for Isa {
+    type Error = Error;
+
+    fn try_from(qcs: &InstructionSetArchitecture) -> Result<Self, Self::Error> {
+        let mut qubits: BTreeMap<u32, isa::Qubit> = qcs
+            .architecture
+            .nodes
+            .iter()
+            .map(|node| {
+                (
+                    node.node_id,
+                    isa::Qubit {
+                        id: node.node_id,
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect();
+        let mut edges: BTreeMap<(u32, u32), isa::Edge> = qcs
+            .architecture
+            .edges
+            .iter()
+            .map(|edge| match edge.node_ids.as_slice() {
+                &[a, b] => {
+                    let (a, b) = (a.min(b), a.max(b));
+                    Ok((
+                        (a, b),
+                        isa::Edge {
+                            ids: [a, b],
+                            ..Default::default()
+                        },
+                    ))
+                }
+                _ => Err(Error::MalformedEdge {
+                    node_ids: edge.node_ids.clone(),
+                }),
+            })
+            .collect::<Result<_, _>>()?;
+
+        for operation in &qcs.instructions {
+            for site in &operation.sites {
+                match site.node_ids.as_slice() {
+                    &[node] => {
+                        let qubit = qubits.get_mut(&node).ok_or_else(|| Error::UnknownNode {
+                            operation: operation.name.clone(),
+                            node,
+                        })?;
+                        match operation.name.as_str() {
+                            "RX" => {
+                                let fidelity =
+                                    qcs.rb_1q_fidelity(node).unwrap_or(DEFAULT_RX_FIDELITY);
+                                qubit.gates.extend(rx_gates(node, fidelity));
+                            }
+                            "RZ" => qubit.gates.push(rz_gate(node)),
+                            "MEASURE" => {
+                                let fidelity = InstructionSetArchitecture::site_characteristic(
+                                    operation, site, "fRO",
+                                )
+                                .unwrap_or(DEFAULT_MEASURE_FIDELITY);
+                                qubit.gates.extend(measure_operations(node, fidelity));
+                            }
+                            _ => {}
+                        }
+                    }
+                    &[a, b] => {
+                        let Some((parameters, default_fidelity)) = two_qubit_gate(&operation.name)
+                        else {
+                            continue;
+                        };
+                        let edge = edges.get_mut(&(a.min(b), a.max(b))).ok_or_else(|| {
+                            Error::UnknownEdge {
+                                operation: operation.name.clone(),
+                                a,
+                                b,
+                            }
+                        })?;
+                        let fidelity = InstructionSetArchitecture::site_characteristic(
+                            operation,
+                            site,
+                            &format!("f{}", operation.name),
+                        )
+                        .unwrap_or(default_fidelity);
+                        edge.gates.push(Operation::Gate(GateInfo {
+                            operator: operation.name.clone(),
+                            parameters,
+                            arguments: vec![Argument::wildcard(), Argument::wildcard()],
+                            duration: Some(DEFAULT_2Q_DURATION),
+                            fidelity: Some(fidelity),
+                        }));
+                    }
+                    nodes => {
+                        return Err(Error::UnsupportedSite {
+                            operation: operation.name.clone(),
+                            count: nodes.len(),
+                        })
+                    }
+                }
+            }
+        }
+
+        let mut isa = Isa::default();
+        for (_, mut qubit) in qubits {
+            qubit.dead = qubit.gates.is_empty();
+            isa.insert_qubit(qubit);
+        }
+        for (_, mut edge) in edges {
+            edge.dead = edge.gates.is_empty();
+            isa.insert_edge(edge);
+        }
+        Ok(isa)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quilc::fixtures::read_data_file;
+
+    fn read_qcs_isa() -> InstructionSetArchitecture {
+        read_data_file("qcs-isa.json").parse().unwrap()
+    }
+
+    #[test]
+    fn test_qcs_isa_fidelities() {
+        let isa = Isa::try_from(&read_qcs_isa()).unwrap();
+
+        let qubit = isa.qubit(0).unwrap();
+        assert!(!qubit.dead);
+        let rx_pi = qubit
+            .gates
+            .iter()
+            .find(|g| {
+                matches!(g, Operation::Gate(GateInfo { operator, parameters, .. })
+                    if operator == "RX" && parameters == &[Parameter::Value(PI)])
+            })
+            .unwrap();
+        assert_eq!(rx_pi.fidelity(), Some(0.995));
+        let measure = qubit
+            .gates
+            .iter()
+            .find(|g| g.operator() == "MEASURE")
+            .unwrap();
+        assert_eq!(measure.fidelity(), Some(0.94));
+
+        let edge = isa.edge(1, 0).unwrap();
+        assert_eq!(edge.ids, [0, 1]);
+        let operators = edge
+            .gates
+            .iter()
+            .map(Operation::operator)
+            .collect::<Vec<_>>();
+        assert_eq!(operators, vec!["CZ", "XY"]);
+        assert_eq!(edge.gates[0].fidelity(), Some(0.95));
+        // No fXY characteristic: falls back to the nominal XY fidelity
+        assert_eq!(edge.gates[1].fidelity(), Some(0.86));
+    }
+
+    #[test]
+    fn test_qcs_isa_dead_elements() {
+        let isa = Isa::try_from(&read_qcs_isa()).unwrap();
+        assert!(isa.qubit(3).unwrap().dead);
+        assert!(isa.edge(2, 3).unwrap().dead);
+        assert!(!isa.edge(1, 2).unwrap().dead);
+    }
+
+    #[test]
+    fn test_qcs_isa_malformed_edge() {
+        let mut qcs = read_qcs_isa();
+        qcs.architecture.edges[0].node_ids.push(3);
+        assert!(matches!(
+            Isa::try_from(&qcs),
+            Err(Error::MalformedEdge { node_ids }) if node_ids.len() == 3
+        ));
+    }
+
+    #[test]
+    fn test_qcs_isa_unknown_node() {
+        let mut qcs = read_qcs_isa();
+        qcs.instructions[0].sites.push(OperationSite {
+            node_ids: vec![42],
+            characteristics: vec![],
+        });
+        assert!(matches!(
+            Isa::try_from(&qcs),
+            Err(Error::UnknownNode { node: 42, .. })
+        ));
+    }
+}
@@ -0,0 +1,184 @@
+//! Graphviz rendering of chip topologies, for inspecting devices and routing results
+
+use std::{collections::BTreeMap, fmt::Write};
+
+use super::{
+    isa::{Isa, Operation},
+    placement::fixed_index,
+    Chip, CompilationResult, Error,
+};
+
+const DEAD_COLOR: &str = "gray";
+const USED_FILL_COLOR: &str = "#f4a261";
+const USED_EDGE_COLOR: &str = "#e76f51";
+
+/// The physical qubits used by a compiled program, highlighted when rendering a chip
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Overlay {
+    /// Each physical qubit used by the program, with the logical qubit that ends up
+    /// on it according to `CompilationMetadata::final_rewiring`, if known
+    pub used_qubits: BTreeMap<u32, Option<u32>>,
+}
+
+impl TryFrom<&CompilationResult> for Overlay {
+    type Error = Error;
+
+    fn try_from(result: &CompilationResult) -> Result<Self, Self::Error> {
+        let program = result.program.to_quil_rs()?;
+        let logical = result
+            .metadata
+            .as_ref()
+            .map(|metadata| {
+                metadata
+                    .final_rewiring
+                    .iter()
+                    .enumerate()
+                    .map(|(logical, &physical)| (physical, logical as u32))
+                    .collect::<BTreeMap<_, _>>()
+            })
+            .unwrap_or_default();
+        let used_qubits = program
+            .get_used_qubits()
+            .iter()
+            .filter_map(fixed_index)
+            .map(|physical| (physical, logical.get(&physical).copied()))
+            .collect();
+        Ok(Self { used_qubits })
+    }
+}
+
+/// The lowest fidelity of each operator, skipping operators that are always perfect
+/// (such as virtual `RZ`s)
+fn fidelity_summary(operations: &[Operation]) -> Vec<String> {
+    let mut fidelities: BTreeMap<&str, f64> = BTreeMap::new();
+    for operation in operations {
+        if let Some(fidelity) = operation.fidelity() {
+            let entry = fidelities.entry(operation.operator()).or_insert(fidelity);
+            *entry = entry.min(fidelity);
+        }
+    }
+    fidelities
+        .into_iter()
+        .filter(|(_, fidelity)| *fidelity < 1.0)
+        .map(|(operator, fidelity)| format!("{operator} {fidelity:.3}"))
+        .collect()
+}
+
+/// Render the ISA as an undirected Graphviz graph. Nodes and edges are labelled with
+/// the lowest fidelity of each native operator; dead elements are drawn dashed and gray.
+///
+/// ISA JSON can be rendered by parsing it into a [`TargetDevice`](super::isa::TargetDevice) first.
+pub fn isa_to_dot(isa: &Isa, overlay: Option<&Overlay>) -> String {
+    let used = |id: u32| overlay.and_then(|overlay| overlay.used_qubits.get(&id));
+    let mut dot = String::from("graph chip {\n    node [shape=circle];\n");
+
+    let mut qubits = isa.qubits.values().collect::<Vec<_>>();
+    qubits.sort_by_key(|qubit| qubit.id);
+    for qubit in qubits {
+        let mut label = vec![qubit.id.to_string()];
+        label.extend(fidelity_summary(&qubit.gates));
+        let mut attributes = vec![];
+        if let Some(logical) = used(qubit.id) {
+            if let Some(logical) = logical {
+                label.push(format!("logical {logical}"));
+            }
+            attributes.push("style=filled".to_string());
+            attributes.push(format!("fillcolor=\"{USED_FILL_COLOR}\""));
+        } else if qubit.dead {
+            attributes.push("style=dashed".to_string());
+            attributes.push(format!("color={DEAD_COLOR}"));
+            attributes.push(format!("fontcolor={DEAD_COLOR}"));
+        }
+        attributes.insert(0, format!("label=\"{}\"", label.join("\\n")));
+        let _ = writeln!(dot, "    {} [{}];", qubit.id, attributes.join(", "));
+    }
+
+    let mut edges = isa.edges.values().collect::<Vec<_>>();
+    edges.sort_by_key(|edge| edge.ids);
+    for edge in edges {
+        let [a, b] = edge.ids;
+        let mut attributes = vec![format!(
+            "label=\"{}\"",
+            fidelity_summary(&edge.gates).join("\\n")
+        )];
+        if used(a).is_some() && used(b).is_some() && !edge.dead {
+            attributes.push(format!("color=\"{USED_EDGE_COLOR}\""));
+            attributes.push("penwidth=2".to_string());
+        } else if edge.dead {
+            attributes.push("style=dashed".to_string());
+            attributes.push(format!("color={DEAD_COLOR}"));
+        }
+        let _ = writeln!(dot, "    {a} -- {b} [{}];", attributes.join(", "));
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+/// Lay out and render the ISA as an SVG document, using the same annotations as [`isa_to_dot`]
+#[cfg(feature = "svg")]
+pub fn isa_to_svg(isa: &Isa, overlay: Option<&Overlay>) -> Result<String, Error> {
+    use layout::{backends::svg::SVGWriter, gv};
+
+    let dot = isa_to_dot(isa, overlay);
+    let graph = gv::DotParser::new(&dot)
+        .process()
+        .map_err(Error::RenderChip)?;
+    let mut builder = gv::GraphBuilder::new();
+    builder.visit_graph(&graph);
+    let mut svg = SVGWriter::new();
+    builder.get().do_it(false, false, false, &mut svg);
+    Ok(svg.finalize())
+}
+
+impl Chip {
+    /// Render the chip as a Graphviz graph; see [`isa_to_dot`]
+    pub fn to_dot(&self, overlay: Option<&Overlay>) -> Result<String, Error> {
+        Ok(isa_to_dot(self.require_isa()?, overlay))
+    }
+
+    /// Render the chip as an SVG document; see [`isa_to_svg`]
+    #[cfg(feature = "svg")]
+    pub fn to_svg(&self, overlay: Option<&Overlay>) -> Result<String, Error> {
+        isa_to_svg(self.require_isa()?, overlay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quilc::fixtures::aspen_9_device;
+
+    #[test]
+    fn test_isa_to_dot_annotations() {
+        let isa = aspen_9_device().isa.restrict_to(&[0, 1, 7]).unwrap();
+        let dot = isa_to_dot(&isa, None);
+
+        assert!(dot.starts_with("graph chip {"));
+        assert!(dot.contains("    3 [label=\"3\\nMEASURE 0.938\\nRX 0.986\", style=dashed"));
+        assert!(dot.contains("    0 -- 1 [label=\""));
+        assert!(dot.contains("    1 -- 2 [label=\"\", style=dashed, color=gray];"));
+    }
+
+    #[test]
+    fn test_isa_to_dot_overlay() {
+        let isa = aspen_9_device().isa;
+        let overlay = Overlay {
+            used_qubits: [(0, Some(1)), (1, Some(0))].into(),
+        };
+        let dot = isa_to_dot(&isa, Some(&overlay));
+
+        assert!(dot.contains("logical 1\", style=filled"));
+        let edge = dot.lines().find(|l| l.starts_with("    0 -- 1 ")).unwrap();
+        assert!(edge.contains("penwidth=2"));
+        let edge = dot.lines().find(|l| l.starts_with("    0 -- 7 ")).unwrap();
+        assert!(!edge.contains("penwidth=2"));
+    }
+
+    #[cfg(feature = "svg")]
+    #[test]
+    fn test_isa_to_svg() {
+        let svg = isa_to_svg(&aspen_9_device().isa, None).unwrap();
+        assert!(svg.contains("<svg"));
+    }
+}
@@ -0,0 +1,321 @@
+//! A before-and-after account of a compilation, for reading or logging
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use super::{
+    metadata, placement::fixed_qubits, Chip, CompilationResult, Compiler, Error, GateStats,
+    Program, ProgramSummary,
+};
+
+/// How a compilation changed a program, produced by [`Compiler::compile_with_report`]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct CompilationReport {
+    /// The number of applications of each gate in the input, by name
+    pub input_gate_counts: BTreeMap<String, usize>,
+    /// The number of applications of each gate in the output, by name
+    pub output_gate_counts: BTreeMap<String, usize>,
+    pub input: GateStats,
+    pub output: GateStats,
+    /// The SWAPs quilc inserted to route the program. This is quilc's own count when it
+    /// reports one (protoquil compiles); otherwise it is the fewest SWAPs that account for
+    /// the changes between the rewirings quilc recorded in the program, which is a lower
+    /// bound. `None` if the program records no rewiring.
+    pub swap_overhead: Option<u32>,
+    /// The physical qubits the output uses, in ascending order
+    pub qubits: Vec<u32>,
+    /// Where each logical qubit ends up, from
+    /// [`CompilationMetadata::final_rewiring`](super::CompilationMetadata::final_rewiring)
+    pub logical_to_physical: BTreeMap<u32, u32>,
+    /// The product of the fidelities of the output's gates, if the chip's ISA gives them
+    pub estimated_fidelity: Option<f64>,
+    /// Wall-clock time spent compiling, including any cache lookup
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "compile_time_seconds", serialize_with = "as_seconds")
+    )]
+    pub compile_time: Duration,
+}
+
+#[cfg(feature = "serde")]
+fn as_seconds<S: serde::Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+/// The fewest transpositions that take each qubit from its place in `from` to its place
+/// in `to`. The moves form chains, which end on a qubit nothing moves away from and
+/// take one transposition per move, and cycles, which take one fewer than their length.
+fn transpositions(from: &[u32], to: &[u32]) -> u32 {
+    let moves = from
+        .iter()
+        .zip(to)
+        .filter(|(a, b)| a != b)
+        .map(|(&a, &b)| (a, b))
+        .collect::<HashMap<_, _>>();
+    let targets = moves.values().copied().collect::<HashSet<_>>();
+    let mut visited = HashSet::new();
+    let mut count = 0;
+
+    // Walk each chain from its start, a qubit nothing moves onto
+    for &start in moves.keys().filter(|qubit| !targets.contains(qubit)) {
+        let mut current = start;
+        while let Some(&next) = moves.get(&current) {
+            visited.insert(current);
+            count += 1;
+            current = next;
+        }
+    }
+    // Every qubit left is on a cycle
+    for &start in moves.keys() {
+        let mut current = start;
+        let mut length = 0;
+        while visited.insert(current) {
+            length += 1;
+            current = moves[&current];
+        }
+        count += length.max(1) - 1;
+    }
+    count
+}
+
+impl CompilationReport {
+    pub(crate) fn new(
+        input: &quil_rs::Program,
+        result: &CompilationResult,
+        chip: &Chip,
+        compile_time: Duration,
+    ) -> Result<Self, Error> {
+        let output = result.program.to_quil_rs()?;
+        let metadata = result
+            .metadata
+            .clone()
+            .unwrap_or_else(|| metadata::estimate(&output, chip.isa()));
+        let swap_overhead = metadata.topological_swaps.or_else(|| {
+            let rewirings = metadata::rewirings(&output);
+            (!rewirings.is_empty()).then(|| {
+                rewirings
+                    .windows(2)
+                    .map(|pair| transpositions(&pair[0], &pair[1]))
+                    .sum()
+            })
+        });
+
+        Ok(Self {
+            input_gate_counts: ProgramSummary::of(input).gate_counts,
+            output_gate_counts: ProgramSummary::of(&output).gate_counts,
+            input: GateStats::of(input),
+            output: GateStats::of(&output),
+            swap_overhead,
+            qubits: fixed_qubits(&output),
+            logical_to_physical: (0..).zip(metadata.final_rewiring.iter().copied()).collect(),
+            estimated_fidelity: metadata.program_fidelity,
+            compile_time,
+        })
+    }
+
+    /// The report as a JSON object, with the compile time in seconds
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+/// Renders the report as a plain-text table
+impl Display for CompilationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn cell<T: Display>(value: Option<T>) -> String {
+            value.map_or_else(|| "-".to_string(), |value| value.to_string())
+        }
+
+        let names = self
+            .input_gate_counts
+            .keys()
+            .chain(self.output_gate_counts.keys())
+            .collect::<BTreeSet<_>>();
+        let mut rows = vec![
+            (
+                "gates".to_string(),
+                self.input.gate_count.to_string(),
+                self.output.gate_count.to_string(),
+            ),
+            (
+                "2Q gates".to_string(),
+                self.input.multiqubit_gate_count.to_string(),
+                self.output.multiqubit_gate_count.to_string(),
+            ),
+            (
+                "depth".to_string(),
+                cell(self.input.gate_depth),
+                cell(self.output.gate_depth),
+            ),
+            (
+                "2Q depth".to_string(),
+                cell(self.input.multiqubit_gate_depth),
+                cell(self.output.multiqubit_gate_depth),
+            ),
+        ];
+        rows.extend(names.into_iter().map(|name| {
+            let count = |counts: &BTreeMap<String, usize>| counts.get(name).copied().unwrap_or(0);
+            (
+                format!("  {name}"),
+                count(&self.input_gate_counts).to_string(),
+                count(&self.output_gate_counts).to_string(),
+            )
+        }));
+
+        let width = rows
+            .iter()
+            .map(|(label, ..)| label.len())
+            .max()
+            .unwrap_or(0);
+        let column = rows
+            .iter()
+            .flat_map(|(_, input, output)| [input.len(), output.len()])
+            .chain(["output".len()])
+            .max()
+            .unwrap_or(0);
+        writeln!(
+            f,
+            "{:width$}  {:>column$}  {:>column$}",
+            "", "input", "output"
+        )?;
+        for (label, input, output) in &rows {
+            writeln!(f, "{label:width$}  {input:>column$}  {output:>column$}")?;
+        }
+
+        let qubits = self.qubits.iter().map(u32::to_string).collect::<Vec<_>>();
+        let rewiring = self
+            .logical_to_physical
+            .iter()
+            .filter(|(logical, physical)| logical != physical)
+            .map(|(logical, physical)| format!("{logical}->{physical}"))
+            .collect::<Vec<_>>();
+        writeln!(f, "SWAP overhead: {}", cell(self.swap_overhead))?;
+        writeln!(f, "qubits: {}", qubits.join(" "))?;
+        if rewiring.is_empty() {
+            writeln!(f, "rewiring: identity")?;
+        } else {
+            writeln!(f, "rewiring: {}", rewiring.join(" "))?;
+        }
+        writeln!(f, "estimated fidelity: {}", cell(self.estimated_fidelity))?;
+        write!(f, "compile time: {:.3}s", self.compile_time.as_secs_f64())
+    }
+}
+
+impl Compiler<'_> {
+    /// Compile the program as [`Compiler::compile`] does, and report how it changed. The
+    /// report uses the result's metadata if the options ask for it, and otherwise derives
+    /// the same figures from the compiled program and the chip's ISA.
+    pub fn compile_with_report(
+        &self,
+        program: &Program,
+    ) -> Result<(CompilationResult, CompilationReport), Error> {
+        let input = program.to_quil_rs()?;
+        let start = Instant::now();
+        let result = self.compile(program)?;
+        let report = CompilationReport::new(&input, &result, self.chip(), start.elapsed())?;
+        Ok((result, report))
+    }
+}
+
+/// Compile the program for the chip with default options and report how it changed; see
+/// [`Compiler::compile_with_report`]
+pub fn compile_with_report(
+    program: &Program,
+    chip: &Chip,
+) -> Result<(CompilationResult, CompilationReport), Error> {
+    Compiler::new(chip).compile_with_report(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quilc::fixtures::aspen_9;
+
+    #[test]
+    fn test_transpositions() {
+        assert_eq!(transpositions(&[0, 1, 2, 3], &[0, 1, 2, 3]), 0);
+        assert_eq!(transpositions(&[0, 1, 2, 3], &[1, 0, 2, 3]), 1);
+        assert_eq!(transpositions(&[0, 1, 2, 3], &[1, 2, 3, 0]), 3);
+        assert_eq!(transpositions(&[0, 1, 2, 3], &[1, 0, 3, 2]), 2);
+        // Chains, counted the same whichever qubit the walk meets first
+        assert_eq!(transpositions(&[0, 1], &[1, 2]), 2);
+        assert_eq!(transpositions(&[2, 0, 1], &[3, 1, 2]), 3);
+        assert_eq!(transpositions(&[0, 1, 5], &[1, 2, 6]), 3);
+    }
+
+    fn example_report() -> CompilationReport {
+        CompilationReport {
+            input_gate_counts: BTreeMap::from([("CNOT".to_string(), 1), ("H".to_string(), 1)]),
+            output_gate_counts: BTreeMap::from([("CZ".to_string(), 1), ("RX".to_string(), 2)]),
+            input: GateStats {
+                gate_count: 2,
+                multiqubit_gate_count: 1,
+                gate_depth: Some(2),
+                multiqubit_gate_depth: Some(1),
+            },
+            output: GateStats {
+                gate_count: 3,
+                multiqubit_gate_count: 1,
+                gate_depth: Some(3),
+                multiqubit_gate_depth: Some(1),
+            },
+            swap_overhead: Some(0),
+            qubits: vec![0, 1],
+            logical_to_physical: BTreeMap::from([(0, 1), (1, 0)]),
+            estimated_fidelity: None,
+            compile_time: Duration::from_millis(1500),
+        }
+    }
+
+    #[test]
+    fn test_report_rendering() {
+        assert_eq!(
+            example_report().to_string(),
+            "           input  output
+gates          2       3
+2Q gates       1       1
+depth          2       3
+2Q depth       1       1
+  CNOT         1       0
+  CZ           0       1
+  H            1       0
+  RX           0       2
+SWAP overhead: 0
+qubits: 0 1
+rewiring: 0->1 1->0
+estimated fidelity: -
+compile time: 1.500s"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_report_json() {
+        let json = example_report().to_json().unwrap();
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(json["compile_time_seconds"], 1.5);
+        assert_eq!(json["logical_to_physical"]["0"], 1);
+        assert_eq!(json["output"]["gate_count"], 3);
+        assert_eq!(json["output_gate_counts"]["RX"], 2);
+    }
+
+    #[test]
+    fn test_compile_with_report() {
+        let chip = aspen_9();
+        let program: Program = "H 0\nCNOT 0 1".parse().unwrap();
+
+        let (result, report) = compile_with_report(&program, &chip).unwrap();
+        assert_eq!(report.input_gate_counts["CNOT"], 1);
+        assert!(!report.output_gate_counts.contains_key("CNOT"));
+        assert_eq!(
+            report.logical_to_physical.len(),
+            result.metadata.unwrap().final_rewiring.len()
+        );
+        assert!(report.estimated_fidelity.is_some());
+    }
+}
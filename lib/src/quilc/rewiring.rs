@@ -0,0 +1,367 @@
+//! Translating between the logical qubits of a program and the physical qubits quilc
+//! placed them on
+
+use std::{collections::HashMap, ops::AddAssign};
+
+use num_complex::Complex64;
+
+use super::{CompilationMetadata, CompilationResult, Error};
+
+/// The largest magnitude of an amplitude treated as zero
+const AMPLITUDE_TOLERANCE: f64 = 1e-10;
+
+/// A one-to-one map from logical to physical qubits, such as
+/// [`CompilationMetadata::final_rewiring`], where logical qubit `l` is on physical qubit
+/// `logical_to_physical[l]`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rewiring {
+    logical_to_physical: Vec<u32>,
+    physical_to_logical: HashMap<u32, u32>,
+}
+
+/// Shots from [`qvm::multishot_measure`](crate::qvm::multishot_measure), with the
+/// columns in logical qubit order
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogicalMeasurements {
+    /// The logical qubit of each column, in ascending order
+    pub qubits: Vec<u32>,
+    /// One row per trial
+    pub shots: Vec<Vec<i32>>,
+}
+
+impl Rewiring {
+    /// Fails with [`Error::InvalidRewiring`] if two logical qubits share a physical qubit
+    pub fn new(logical_to_physical: Vec<u32>) -> Result<Self, Error> {
+        let mut physical_to_logical = HashMap::with_capacity(logical_to_physical.len());
+        for (logical, &physical) in (0..).zip(&logical_to_physical) {
+            if let Some(other) = physical_to_logical.insert(physical, logical) {
+                return Err(Error::InvalidRewiring(format!(
+                    "logical qubits {other} and {logical} are both on physical qubit {physical}"
+                )));
+            }
+        }
+        Ok(Self {
+            logical_to_physical,
+            physical_to_logical,
+        })
+    }
+
+    /// The rewiring that leaves each of the first `n` qubits in place
+    pub fn identity(n: u32) -> Self {
+        Self {
+            logical_to_physical: (0..n).collect(),
+            physical_to_logical: (0..n).map(|qubit| (qubit, qubit)).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.logical_to_physical.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.logical_to_physical.is_empty()
+    }
+
+    pub fn logical_to_physical(&self) -> &[u32] {
+        &self.logical_to_physical
+    }
+
+    /// The physical qubit holding `logical`, if the rewiring covers it
+    pub fn physical(&self, logical: u32) -> Option<u32> {
+        self.logical_to_physical.get(logical as usize).copied()
+    }
+
+    /// The logical qubit held by `physical`, if any
+    pub fn logical(&self, physical: u32) -> Option<u32> {
+        self.physical_to_logical.get(&physical).copied()
+    }
+
+    /// The rewiring from physical back to logical qubits. Fails with
+    /// [`Error::InvalidRewiring`] unless the physical qubits are exactly `0..len`.
+    pub fn inverse(&self) -> Result<Self, Error> {
+        (0..self.len() as u32)
+            .map(|physical| {
+                self.logical(physical).ok_or_else(|| {
+                    Error::InvalidRewiring(format!(
+                        "no logical qubit is on physical qubit {physical}"
+                    ))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .and_then(Self::new)
+    }
+
+    /// The rewiring that applies `self` and then `next`, treating the physical qubits of
+    /// `self` as the logical qubits of `next`. Fails with [`Error::InvalidRewiring`] if
+    /// `next` does not cover every physical qubit of `self`.
+    pub fn then(&self, next: &Rewiring) -> Result<Self, Error> {
+        self.logical_to_physical
+            .iter()
+            .map(|&qubit| {
+                next.physical(qubit).ok_or_else(|| {
+                    Error::InvalidRewiring(format!(
+                        "qubit {qubit} is not covered by the next rewiring"
+                    ))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .and_then(Self::new)
+    }
+
+    /// The logical qubit on each physical qubit of a vector of `len` entries indexed by
+    /// physical basis states
+    fn simulated_logical_qubits(&self, len: usize) -> Result<Vec<u32>, Error> {
+        if !len.is_power_of_two() {
+            return Err(Error::InvalidRewiring(format!(
+                "{len} entries is not a power of two"
+            )));
+        }
+        (0..len.trailing_zeros())
+            .map(|physical| {
+                self.logical(physical).ok_or_else(|| {
+                    Error::InvalidRewiring(format!(
+                        "no logical qubit is on physical qubit {physical}"
+                    ))
+                })
+            })
+            .collect()
+    }
+
+    /// Reorder a vector indexed by physical basis states, with bit `p` of the index the
+    /// state of physical qubit `p`, into one of length `2^qubits` where bit `l` is the
+    /// state of logical qubit `l`. Logical qubits from `qubits` up are traced out by
+    /// summing over their states; logical qubits below it on physical qubits that were not
+    /// simulated are in `|0>`.
+    fn to_logical_order<T: Copy + Default + AddAssign>(
+        &self,
+        values: &[T],
+        qubits: u32,
+    ) -> Result<Vec<T>, Error> {
+        let logical = self.simulated_logical_qubits(values.len())?;
+        let mut reordered = vec![T::default(); 1 << qubits];
+        for (index, &value) in values.iter().enumerate() {
+            let target = logical
+                .iter()
+                .enumerate()
+                .filter(|&(physical, &l)| l < qubits && index & (1 << physical) != 0)
+                .fold(0, |target, (_, &l)| target | (1 << l));
+            reordered[target] += value;
+        }
+        Ok(reordered)
+    }
+
+    /// Reorder the output of [`qvm::wavefunction`](crate::qvm::wavefunction) for a
+    /// compiled program so that it is indexed by the first `qubits` logical qubits.
+    /// Every other qubit must be in `|0>`, as it is when the program does not use it;
+    /// a nonzero amplitude with any of them outside `|0>` fails with
+    /// [`Error::InvalidRewiring`], since those qubits cannot be traced out of a
+    /// wavefunction.
+    pub fn wavefunction_to_logical(
+        &self,
+        wavefunction: &[Complex64],
+        qubits: u32,
+    ) -> Result<Vec<Complex64>, Error> {
+        let logical = self.simulated_logical_qubits(wavefunction.len())?;
+        let traced = logical
+            .iter()
+            .enumerate()
+            .filter(|&(_, &l)| l >= qubits)
+            .fold(0, |mask, (physical, _)| mask | (1 << physical));
+        if let Some(index) = (0..wavefunction.len())
+            .find(|&index| index & traced != 0 && wavefunction[index].norm() > AMPLITUDE_TOLERANCE)
+        {
+            return Err(Error::InvalidRewiring(format!(
+                "basis state {index} has a nonzero amplitude with a traced-out qubit outside |0>"
+            )));
+        }
+        self.to_logical_order(wavefunction, qubits)
+    }
+
+    /// Reorder the output of [`qvm::probabilities`](crate::qvm::probabilities) for a
+    /// compiled program so that it is indexed by the first `qubits` logical qubits,
+    /// marginalizing over any other qubit
+    pub fn probabilities_to_logical(
+        &self,
+        probabilities: &[f64],
+        qubits: u32,
+    ) -> Result<Vec<f64>, Error> {
+        self.to_logical_order(probabilities, qubits)
+    }
+
+    /// The physical qubits to pass to [`qvm::multishot_measure`](crate::qvm::multishot_measure)
+    /// to measure the given logical qubits
+    pub fn to_physical(&self, logical: &[u32]) -> Result<Vec<i32>, Error> {
+        logical
+            .iter()
+            .map(|&qubit| {
+                self.physical(qubit)
+                    .map(|physical| physical as i32)
+                    .ok_or_else(|| {
+                        Error::InvalidRewiring(format!("logical qubit {qubit} is not rewired"))
+                    })
+            })
+            .collect()
+    }
+
+    /// Reorder the columns of [`qvm::multishot_measure`](crate::qvm::multishot_measure)
+    /// output, measured on the physical `qubits`, into ascending logical qubit order.
+    /// Every shot must have one value per qubit.
+    pub fn measurements_to_logical(
+        &self,
+        qubits: &[i32],
+        shots: &[Vec<i32>],
+    ) -> Result<LogicalMeasurements, Error> {
+        let mut columns = qubits
+            .iter()
+            .enumerate()
+            .map(|(column, &physical)| {
+                u32::try_from(physical)
+                    .ok()
+                    .and_then(|physical| self.logical(physical))
+                    .map(|logical| (logical, column))
+                    .ok_or_else(|| {
+                        Error::InvalidRewiring(format!(
+                            "no logical qubit is on physical qubit {physical}"
+                        ))
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        columns.sort_unstable();
+        if let Some(row) = shots.iter().position(|shot| shot.len() != qubits.len()) {
+            return Err(Error::InvalidRewiring(format!(
+                "shot {row} has {} values for {} measured qubits",
+                shots[row].len(),
+                qubits.len()
+            )));
+        }
+
+        Ok(LogicalMeasurements {
+            qubits: columns.iter().map(|&(logical, _)| logical).collect(),
+            shots: shots
+                .iter()
+                .map(|shot| columns.iter().map(|&(_, column)| shot[column]).collect())
+                .collect(),
+        })
+    }
+}
+
+impl CompilationMetadata {
+    /// The [`final_rewiring`](Self::final_rewiring) as a [`Rewiring`]
+    pub fn rewiring(&self) -> Result<Rewiring, Error> {
+        Rewiring::new(self.final_rewiring.clone())
+    }
+}
+
+impl CompilationResult {
+    /// The final rewiring of the compiled program. Fails with [`Error::MissingMetadata`]
+    /// if the result was compiled without metadata.
+    pub fn rewiring(&self) -> Result<Rewiring, Error> {
+        self.metadata
+            .as_ref()
+            .ok_or(Error::MissingMetadata)?
+            .rewiring()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookups_and_composition() {
+        let rewiring = Rewiring::new(vec![2, 0, 1]).unwrap();
+        assert_eq!(rewiring.physical(0), Some(2));
+        assert_eq!(rewiring.logical(2), Some(0));
+        assert_eq!(rewiring.logical(3), None);
+
+        let inverse = rewiring.inverse().unwrap();
+        assert_eq!(inverse.logical_to_physical(), &[1, 2, 0]);
+        assert_eq!(rewiring.then(&inverse).unwrap(), Rewiring::identity(3));
+        assert_eq!(
+            rewiring.then(&rewiring).unwrap().logical_to_physical(),
+            &[1, 2, 0]
+        );
+
+        assert!(matches!(
+            Rewiring::new(vec![0, 0]),
+            Err(Error::InvalidRewiring(_))
+        ));
+        assert!(matches!(
+            Rewiring::new(vec![0, 5]).unwrap().inverse(),
+            Err(Error::InvalidRewiring(_))
+        ));
+    }
+
+    #[test]
+    fn test_probabilities_to_logical() {
+        // Logical qubit 0 on physical qubit 1 and logical qubit 1 on physical qubit 0
+        let rewiring = Rewiring::new(vec![1, 0]).unwrap();
+        // Physical qubit 0 certainly in |1>
+        let probabilities = [0.0, 1.0, 0.0, 0.0];
+        assert_eq!(
+            rewiring
+                .probabilities_to_logical(&probabilities, 2)
+                .unwrap(),
+            vec![0.0, 0.0, 1.0, 0.0]
+        );
+        // Tracing out logical qubit 1
+        assert_eq!(
+            rewiring
+                .probabilities_to_logical(&[0.1, 0.2, 0.3, 0.4], 1)
+                .unwrap(),
+            vec![0.1 + 0.2, 0.3 + 0.4]
+        );
+        assert!(rewiring.probabilities_to_logical(&[0.5; 3], 2).is_err());
+
+        // Only physical qubit 0 simulated, holding logical qubit 2
+        let rewiring = Rewiring::new(vec![1, 2, 0]).unwrap();
+        let wavefunction = [Complex64::new(0.0, 0.0), Complex64::new(1.0, 0.0)];
+        let logical = rewiring.wavefunction_to_logical(&wavefunction, 3).unwrap();
+        assert_eq!(logical.len(), 8);
+        assert_eq!(logical[4], Complex64::new(1.0, 0.0));
+
+        // Logical qubit 1, on physical qubit 0, is traced out but not in |0>
+        let rewiring = Rewiring::new(vec![1, 0]).unwrap();
+        let amplitude = Complex64::new(std::f64::consts::FRAC_1_SQRT_2, 0.0);
+        let wavefunction = [
+            amplitude,
+            amplitude,
+            Complex64::default(),
+            Complex64::default(),
+        ];
+        assert!(matches!(
+            rewiring.wavefunction_to_logical(&wavefunction, 1),
+            Err(Error::InvalidRewiring(_))
+        ));
+        assert_eq!(
+            rewiring.wavefunction_to_logical(&wavefunction, 2).unwrap(),
+            vec![
+                amplitude,
+                Complex64::default(),
+                amplitude,
+                Complex64::default()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_measurements_to_logical() {
+        let rewiring = Rewiring::new(vec![3, 1, 0]).unwrap();
+        let qubits = rewiring.to_physical(&[2, 0]).unwrap();
+        assert_eq!(qubits, vec![0, 3]);
+
+        let shots = vec![vec![1, 0], vec![0, 1]];
+        assert_eq!(
+            rewiring.measurements_to_logical(&qubits, &shots).unwrap(),
+            LogicalMeasurements {
+                qubits: vec![0, 2],
+                shots: vec![vec![0, 1], vec![1, 0]],
+            }
+        );
+        assert!(rewiring.measurements_to_logical(&[2], &shots).is_err());
+        assert!(matches!(
+            rewiring.measurements_to_logical(&qubits, &[vec![1]]),
+            Err(Error::InvalidRewiring(_))
+        ));
+    }
+}
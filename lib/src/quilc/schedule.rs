@@ -0,0 +1,245 @@
+//! Timing a native program on a chip: when each instruction runs, what bounds the
+//! program's duration, and how long each qubit waits
+
+use std::collections::{BTreeMap, HashMap};
+
+use quil_rs::instruction::{Instruction, Qubit};
+
+use super::{
+    isa::{real_parameters, Isa},
+    placement::fixed_indices,
+    Chip, Error, Program,
+};
+
+/// When an instruction runs, in the units of the ISA's durations (nanoseconds for QCS
+/// ISAs), with the program starting at zero
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimeWindow {
+    /// The instruction's index among the body instructions of the program as quilc
+    /// prints it, which may differ from the source text it was parsed from
+    pub index: usize,
+    pub qubits: Vec<u32>,
+    pub start: f64,
+    pub end: f64,
+}
+
+/// The output of [`schedule`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schedule {
+    /// One window per gate, measurement, `FENCE` and `DELAY`, in program order
+    pub windows: Vec<TimeWindow>,
+    /// The end of the last window
+    pub duration: f64,
+    /// Indices into `windows` of the chain of dependent instructions that ends last and
+    /// so sets the program's duration, from first to last
+    pub critical_path: Vec<usize>,
+    /// For each qubit, the time between the start of its first instruction and the end
+    /// of its last that it spends waiting on other qubits
+    pub idle_time: BTreeMap<u32, f64>,
+}
+
+fn unschedulable(index: usize, reason: impl Into<String>) -> Error {
+    Error::Unschedulable {
+        index,
+        reason: reason.into(),
+    }
+}
+
+fn fixed(index: usize, qubits: &[Qubit]) -> Result<Vec<u32>, Error> {
+    fixed_indices(qubits).ok_or_else(|| unschedulable(index, "qubit is not a fixed index"))
+}
+
+/// The qubits and duration of an instruction that occupies qubits, or `None` for one that
+/// takes no time on the QPU, such as a declaration or pragma
+fn timing(
+    isa: &Isa,
+    index: usize,
+    instruction: &Instruction,
+) -> Result<Option<(Vec<u32>, f64)>, Error> {
+    let timing = match instruction {
+        Instruction::Gate(gate) => {
+            let qubits = fixed(index, &gate.qubits)?;
+            let parameters = real_parameters(&gate.parameters);
+            let duration = isa
+                .gate(&gate.name, &parameters, &qubits)
+                .filter(|_| gate.modifiers.is_empty())
+                .and_then(|native| native.duration)
+                .ok_or_else(|| unschedulable(index, "gate has no duration in the ISA"))?;
+            Some((qubits, duration))
+        }
+        Instruction::Measurement(measurement) => {
+            let qubits = fixed(index, std::slice::from_ref(&measurement.qubit))?;
+            let duration = isa
+                .measure(qubits[0], measurement.target.is_some())
+                .and_then(|native| native.duration)
+                .ok_or_else(|| unschedulable(index, "measurement has no duration in the ISA"))?;
+            Some((qubits, duration))
+        }
+        Instruction::Delay(delay) => {
+            let duration = delay
+                .duration
+                .clone()
+                .into_simplified()
+                .to_real()
+                .map_err(|_| unschedulable(index, "delay duration is not a number"))?;
+            Some((fixed(index, &delay.qubits)?, duration))
+        }
+        Instruction::Fence(fence) => Some((fixed(index, &fence.qubits)?, 0.0)),
+        Instruction::Reset(_) => return Err(unschedulable(index, "RESET has no duration")),
+        Instruction::Pulse(_) | Instruction::Capture(_) | Instruction::RawCapture(_) => {
+            return Err(unschedulable(
+                index,
+                "pulse-level instructions have no duration in the ISA",
+            ))
+        }
+        Instruction::Label(_)
+        | Instruction::Jump(_)
+        | Instruction::JumpWhen(_)
+        | Instruction::JumpUnless(_)
+        | Instruction::Halt
+        | Instruction::Wait => return Err(unschedulable(index, "control flow")),
+        _ => None,
+    };
+    Ok(timing)
+}
+
+pub(crate) fn schedule_isa(program: &quil_rs::Program, isa: &Isa) -> Result<Schedule, Error> {
+    let mut windows: Vec<TimeWindow> = vec![];
+    // The window that most recently occupied each qubit
+    let mut last: HashMap<u32, usize> = HashMap::new();
+    let mut predecessor = vec![];
+
+    for (index, instruction) in program.body_instructions().enumerate() {
+        let Some((mut qubits, duration)) = timing(isa, index, instruction)? else {
+            continue;
+        };
+        // A FENCE or DELAY without qubits applies to every qubit used so far
+        if qubits.is_empty() {
+            qubits = last.keys().copied().collect();
+            qubits.sort_unstable();
+        }
+        let previous = qubits
+            .iter()
+            .filter_map(|qubit| last.get(qubit).copied())
+            .fold(None, |best: Option<usize>, window| match best {
+                Some(best) if windows[best].end >= windows[window].end => Some(best),
+                _ => Some(window),
+            });
+        let start = previous.map_or(0.0, |window| windows[window].end);
+        for &qubit in &qubits {
+            last.insert(qubit, windows.len());
+        }
+        predecessor.push(previous);
+        windows.push(TimeWindow {
+            index,
+            qubits,
+            start,
+            end: start + duration,
+        });
+    }
+
+    let finish =
+        windows
+            .iter()
+            .enumerate()
+            .fold(None, |best: Option<usize>, (window, w)| match best {
+                Some(best) if windows[best].end >= w.end => Some(best),
+                _ => Some(window),
+            });
+    let mut critical_path =
+        std::iter::successors(finish, |&window| predecessor[window]).collect::<Vec<_>>();
+    critical_path.reverse();
+
+    let mut spans: BTreeMap<u32, (f64, f64, f64)> = BTreeMap::new();
+    for window in &windows {
+        for &qubit in &window.qubits {
+            let (first, last, busy) = spans
+                .entry(qubit)
+                .or_insert((window.start, window.end, 0.0));
+            *first = first.min(window.start);
+            *last = last.max(window.end);
+            *busy += window.end - window.start;
+        }
+    }
+
+    Ok(Schedule {
+        duration: finish.map_or(0.0, |window| windows[window].end),
+        windows,
+        critical_path,
+        idle_time: spans
+            .into_iter()
+            .map(|(qubit, (first, last, busy))| (qubit, last - first - busy))
+            .collect(),
+    })
+}
+
+/// Schedule each instruction of a native program as early as its qubits allow, using
+/// the durations in the chip's ISA.
+///
+/// Every gate and measurement must match a native operation with a duration, and the
+/// program may not contain control flow, `RESET`, `PULSE`, `CAPTURE` or `RAW-CAPTURE`;
+/// otherwise this fails with [`Error::Unschedulable`]. Fails with [`Error::MissingIsa`]
+/// if the chip was not built from an ISA.
+pub fn schedule(program: &Program, chip: &Chip) -> Result<Schedule, Error> {
+    schedule_isa(&program.to_quil_rs()?, chip.require_isa()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quilc::fixtures::aspen_9_device;
+
+    #[test]
+    fn test_schedule() {
+        let program: quil_rs::Program = "DECLARE ro BIT
+RX(pi/2) 0
+RX(pi/2) 7
+RX(pi/2) 7
+CZ 0 7
+MEASURE 0 ro"
+            .parse()
+            .unwrap();
+        let schedule = schedule_isa(&program, &aspen_9_device().isa).unwrap();
+
+        let windows = schedule
+            .windows
+            .iter()
+            .map(|w| (w.index, w.start, w.end))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            windows,
+            vec![
+                (0, 0.0, 50.0),
+                (1, 0.0, 50.0),
+                (2, 50.0, 100.0),
+                (3, 100.0, 300.0),
+                (4, 300.0, 2300.0),
+            ]
+        );
+        assert_eq!(schedule.duration, 2300.0);
+        assert_eq!(schedule.critical_path, vec![1, 2, 3, 4]);
+        assert_eq!(schedule.idle_time, BTreeMap::from([(0, 50.0), (7, 0.0)]));
+    }
+
+    #[test]
+    fn test_unschedulable() {
+        let isa = aspen_9_device().isa;
+        let program: quil_rs::Program = "RX(pi/2) 0\nH 0".parse().unwrap();
+        assert!(matches!(
+            schedule_isa(&program, &isa),
+            Err(Error::Unschedulable { index: 1, .. })
+        ));
+        let program: quil_rs::Program = "LABEL @start\nRX(pi/2) 0".parse().unwrap();
+        assert!(matches!(
+            schedule_isa(&program, &isa),
+            Err(Error::Unschedulable { index: 0, .. })
+        ));
+        let program: quil_rs::Program = "RX(pi/2) 0\nPULSE 0 \"rf\" flat(duration: 1e-6, iq: 1)"
+            .parse()
+            .unwrap();
+        assert!(matches!(
+            schedule_isa(&program, &isa),
+            Err(Error::Unschedulable { index: 1, .. })
+        ));
+    }
+}
@@ -0,0 +1,181 @@
+//! Describing a program's memory, qubits and gates without compiling it
+
+use std::collections::BTreeMap;
+
+use quil_rs::instruction::{Instruction, ScalarType};
+
+use super::{placement::fixed_qubits, Error, GateStats, MemoryType, Program};
+
+impl From<ScalarType> for MemoryType {
+    fn from(value: ScalarType) -> Self {
+        match value {
+            ScalarType::Bit => Self::Bit,
+            ScalarType::Octet => Self::Octet,
+            ScalarType::Integer => Self::Integer,
+            ScalarType::Real => Self::Real,
+        }
+    }
+}
+
+/// A `DECLARE`d memory region
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Declaration {
+    pub name: String,
+    pub memory_type: MemoryType,
+    pub length: u64,
+    /// The region this one aliases, from a `SHARING` clause
+    pub sharing: Option<Sharing>,
+}
+
+/// The `SHARING` clause of a declaration
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sharing {
+    /// The name of the aliased region
+    pub name: String,
+    /// The `OFFSET` pairs, as a count of elements and their type
+    pub offsets: Vec<(u64, MemoryType)>,
+}
+
+/// Figures about a program useful for estimating its cost before compiling it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProgramSummary {
+    /// The program's declarations, in the order they appear
+    pub declarations: Vec<Declaration>,
+    /// The fixed qubits the program uses, in ascending order; placeholders are not included
+    pub qubits: Vec<u32>,
+    /// The number of applications of each gate, by name and regardless of modifiers
+    pub gate_counts: BTreeMap<String, usize>,
+    /// Gate counts and depths, where multiqubit gates are those on 2 or more qubits
+    pub gates: GateStats,
+    pub has_measurements: bool,
+    /// Whether the program has labels, jumps, `HALT` or `WAIT`
+    pub has_control_flow: bool,
+    pub has_pragmas: bool,
+}
+
+impl ProgramSummary {
+    pub(crate) fn of(program: &quil_rs::Program) -> Self {
+        let declarations = program
+            .memory_regions
+            .iter()
+            .map(|(name, region)| Declaration {
+                name: name.clone(),
+                memory_type: region.size.data_type.into(),
+                length: region.size.length,
+                sharing: region.sharing.as_ref().map(|sharing| Sharing {
+                    name: sharing.name.clone(),
+                    offsets: sharing
+                        .offsets
+                        .iter()
+                        .map(|offset| (offset.offset, offset.data_type.into()))
+                        .collect(),
+                }),
+            })
+            .collect();
+
+        let mut gate_counts = BTreeMap::new();
+        let mut has_measurements = false;
+        let mut has_control_flow = false;
+        let mut has_pragmas = false;
+        for instruction in program.body_instructions() {
+            match instruction {
+                Instruction::Gate(gate) => *gate_counts.entry(gate.name.clone()).or_default() += 1,
+                Instruction::Measurement(_) => has_measurements = true,
+                Instruction::Label(_)
+                | Instruction::Jump(_)
+                | Instruction::JumpWhen(_)
+                | Instruction::JumpUnless(_)
+                | Instruction::Halt
+                | Instruction::Wait => has_control_flow = true,
+                Instruction::Pragma(_) => has_pragmas = true,
+                _ => {}
+            }
+        }
+
+        Self {
+            declarations,
+            qubits: fixed_qubits(program),
+            gate_counts,
+            gates: GateStats::of(program),
+            has_measurements,
+            has_control_flow,
+            has_pragmas,
+        }
+    }
+}
+
+impl Program {
+    /// Summarize the program's declarations, qubits and gates
+    pub fn summary(&self) -> Result<ProgramSummary, Error> {
+        Ok(ProgramSummary::of(&self.to_quil_rs()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let program: quil_rs::Program = "DECLARE ro BIT[2]
+DECLARE theta REAL[2]
+DECLARE bits BIT[128] SHARING theta OFFSET 1 REAL
+PRAGMA INITIAL_REWIRING \"NAIVE\"
+RX(theta[0]) 0
+CZ 0 1
+DAGGER RX(pi/2) 1
+CZ 1 2
+MEASURE 0 ro[0]
+MEASURE 2 ro[1]"
+            .parse()
+            .unwrap();
+        let summary = ProgramSummary::of(&program);
+
+        assert_eq!(
+            summary.declarations,
+            vec![
+                Declaration {
+                    name: "ro".to_string(),
+                    memory_type: MemoryType::Bit,
+                    length: 2,
+                    sharing: None,
+                },
+                Declaration {
+                    name: "theta".to_string(),
+                    memory_type: MemoryType::Real,
+                    length: 2,
+                    sharing: None,
+                },
+                Declaration {
+                    name: "bits".to_string(),
+                    memory_type: MemoryType::Bit,
+                    length: 128,
+                    sharing: Some(Sharing {
+                        name: "theta".to_string(),
+                        offsets: vec![(1, MemoryType::Real)],
+                    }),
+                },
+            ]
+        );
+        assert_eq!(summary.qubits, vec![0, 1, 2]);
+        assert_eq!(
+            summary.gate_counts,
+            BTreeMap::from([("CZ".to_string(), 2), ("RX".to_string(), 2)])
+        );
+        assert_eq!(summary.gates.multiqubit_gate_count, 2);
+        assert_eq!(summary.gates.multiqubit_gate_depth, Some(2));
+        assert!(summary.has_measurements);
+        assert!(summary.has_pragmas);
+        assert!(!summary.has_control_flow);
+    }
+
+    #[test]
+    fn test_summary_control_flow() {
+        let program: Program = "DECLARE ro BIT\nLABEL @loop\nH 0\nMEASURE 0 ro\nJUMP-WHEN @loop ro"
+            .parse()
+            .unwrap();
+        let summary = program.summary().unwrap();
+        assert!(summary.has_control_flow);
+        assert_eq!(summary.gates.gate_depth, None);
+    }
+}
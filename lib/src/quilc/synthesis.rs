@@ -0,0 +1,150 @@
+//! Compiling a unitary matrix into native gates
+
+use std::{fmt::Write, str::FromStr};
+
+use num_complex::Complex64;
+
+use super::{
+    compile_program,
+    placement::check_unmoved,
+    unitary::{self, gates_unitary, Matrix},
+    Chip, Error, Placement, Program, RewiringStrategy,
+};
+
+/// The largest entry-wise deviation of `U†U` from the identity accepted as unitary
+const UNITARITY_TOLERANCE: f64 = 1e-8;
+
+/// The output of [`synthesize_unitary`]
+#[derive(Debug)]
+pub struct Synthesis {
+    /// A native program implementing the matrix
+    pub program: Program,
+    /// `1 - |tr(U†V)| / dim` between the target `U` and the program's unitary `V`,
+    /// which is zero when they agree up to a global phase
+    pub distance: f64,
+}
+
+/// Format a complex number as a Quil expression
+fn to_quil(value: Complex64) -> String {
+    let sign = if value.im.is_sign_negative() {
+        '-'
+    } else {
+        '+'
+    };
+    format!("{}{sign}{}i", value.re, value.im.abs())
+}
+
+/// A program defining the matrix as a gate and applying it to `qubits`
+fn defgate_program(matrix: &Matrix, qubits: &[u32]) -> String {
+    let mut text = "DEFGATE SYNTHESIZED:\n".to_string();
+    for row in matrix.data.chunks(matrix.dim) {
+        let row = row.iter().copied().map(to_quil).collect::<Vec<_>>();
+        let _ = writeln!(text, "    {}", row.join(", "));
+    }
+    let qubits = qubits.iter().map(u32::to_string).collect::<Vec<_>>();
+    let _ = writeln!(text, "\nSYNTHESIZED {}", qubits.join(" "));
+    text
+}
+
+/// Compile a unitary into native gates for the chip.
+///
+/// `matrix` holds the `2^n x 2^n` matrix row-major, where `n` is the number of `qubits`
+/// and `qubits[0]` is the most significant bit of the matrix index, as for a `DEFGATE`.
+/// The qubits keep their indices: the program is compiled with the `NAIVE` rewiring
+/// strategy, and [`Error::PlacementMismatch`] is returned if quilc placed them elsewhere
+/// or moved them while routing, as it must when they share no edge.
+pub fn synthesize_unitary(
+    matrix: &[Complex64],
+    qubits: &[u32],
+    chip: &Chip,
+) -> Result<Synthesis, Error> {
+    let target = Matrix::from_entries(matrix)?;
+    if target.qubits() != qubits.len() {
+        return Err(unitary::Error::QubitCountMismatch {
+            expected: 1 << qubits.len(),
+            found: matrix.len(),
+        }
+        .into());
+    }
+    if let Some((index, &value)) = matrix.iter().enumerate().find(|(_, z)| !z.is_finite()) {
+        return Err(unitary::Error::NonFinite { index, value }.into());
+    }
+    let deviation = target.unitarity_deviation();
+    if deviation > UNITARITY_TOLERANCE {
+        return Err(unitary::Error::NotUnitary { deviation }.into());
+    }
+
+    let program = Program::from_str(&defgate_program(&target, qubits))?;
+    let placement = Placement::from(RewiringStrategy::Naive);
+    let result = compile_program(&placement.apply(&program, chip)?, chip)?;
+    placement.check(&result)?;
+    check_unmoved(&result)?;
+
+    let synthesized = gates_unitary(&result.program.to_quil_rs()?, qubits)?;
+    Ok(Synthesis {
+        distance: target.phase_invariant_distance(&synthesized),
+        program: result.program,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quilc::fixtures::aspen_9;
+
+    #[test]
+    fn test_defgate_program() {
+        let entries = [
+            Complex64::new(0.0, 0.0),
+            Complex64::new(0.0, -1.0),
+            Complex64::new(0.0, 1.0),
+            Complex64::new(0.0, 0.0),
+        ];
+        let text = defgate_program(&Matrix::from_entries(&entries).unwrap(), &[3]);
+        assert_eq!(
+            text,
+            "DEFGATE SYNTHESIZED:\n    0+0i, 0-1i\n    0+1i, 0+0i\n\nSYNTHESIZED 3\n"
+        );
+        text.parse::<quil_rs::Program>().unwrap();
+    }
+
+    #[test]
+    fn test_synthesize_unitary() {
+        let chip = aspen_9();
+
+        // CNOT with qubit 1 as the control
+        let one = Complex64::new(1.0, 0.0);
+        let zero = Complex64::default();
+        let matrix = [
+            one, zero, zero, zero, //
+            zero, zero, zero, one, //
+            zero, zero, one, zero, //
+            zero, one, zero, zero,
+        ];
+        let synthesis = synthesize_unitary(&matrix, &[0, 1], &chip).unwrap();
+        assert!(synthesis.distance < 1e-8);
+
+        let not_unitary = [one, one, zero, one];
+        assert!(matches!(
+            synthesize_unitary(&not_unitary, &[0], &chip),
+            Err(Error::Unitary(unitary::Error::NotUnitary { .. }))
+        ));
+        assert!(matches!(
+            synthesize_unitary(&matrix, &[0], &chip),
+            Err(Error::Unitary(unitary::Error::QubitCountMismatch { .. }))
+        ));
+
+        // NaN would otherwise vanish from the largest deviation and pass as unitary
+        let nan = [one, zero, zero, Complex64::new(f64::NAN, 0.0)];
+        assert!(matches!(
+            synthesize_unitary(&nan, &[0], &chip),
+            Err(Error::Unitary(unitary::Error::NonFinite { index: 3, .. }))
+        ));
+
+        // Qubits 0 and 2 share no edge, so quilc would have to move one of them
+        assert!(matches!(
+            synthesize_unitary(&matrix, &[0, 2], &chip),
+            Err(Error::PlacementMismatch { .. })
+        ));
+    }
+}
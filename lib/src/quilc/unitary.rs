@@ -0,0 +1,513 @@
+//! Dense unitary matrices of gate-only programs, for verifying compilations and
+//! debugging gate definitions
+//!
+//! Matrices follow Quil's conventions: the first qubit a gate acts on is the most
+//! significant bit of the matrix index.
+
+use std::collections::HashMap;
+
+#[cfg(feature = "ndarray")]
+use ndarray::Array2;
+use num_complex::Complex64;
+use quil_rs::instruction::{
+    Gate, GateDefinition, GateModifier, GateSpecification, Instruction, Qubit,
+};
+
+use super::isa::real_parameters;
+#[cfg(feature = "ndarray")]
+use super::{placement::fixed_qubits, Program};
+
+/// The qubit limit used by `program_unitary` and
+/// [`verify_compilation`](crate::verify::verify_compilation)
+pub const DEFAULT_MAX_QUBITS: usize = 10;
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum Error {
+    #[error("a matrix of {0} entries is not square with a power-of-two dimension")]
+    InvalidDimension(usize),
+    #[error("expected a {expected}x{expected} matrix for the qubits, found {found} entries")]
+    QubitCountMismatch { expected: usize, found: usize },
+    #[error("matrix entry {index} is not finite: {value}")]
+    NonFinite { index: usize, value: Complex64 },
+    #[error("the matrix is not unitary: U†U differs from the identity by up to {deviation}")]
+    NotUnitary { deviation: f64 },
+    #[error("instruction {index} is not a gate: {instruction}")]
+    NotAGate { index: usize, instruction: String },
+    #[error("instruction {index} applies a gate with no known matrix: {gate}")]
+    UnknownGate { index: usize, gate: String },
+    #[error("instruction {index} has a parameter that is not a real constant")]
+    SymbolicParameter { index: usize },
+    #[error("instruction {index} acts on a qubit that is not one of the fixed qubits considered")]
+    UnexpectedQubit { index: usize },
+    #[error("instruction {index} is a measurement, which has no unitary")]
+    Measurement { index: usize },
+    #[error("instruction {index} is classical control flow, which has no unitary")]
+    ControlFlow { index: usize },
+    #[error("the program uses {qubits} qubits, more than the limit of {limit}")]
+    TooManyQubits { qubits: usize, limit: usize },
+}
+
+/// A square complex matrix stored row-major
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Matrix {
+    pub(crate) dim: usize,
+    pub(crate) data: Vec<Complex64>,
+}
+
+fn c(re: f64, im: f64) -> Complex64 {
+    Complex64::new(re, im)
+}
+
+impl Matrix {
+    pub(crate) fn identity(dim: usize) -> Self {
+        let mut data = vec![Complex64::default(); dim * dim];
+        for i in 0..dim {
+            data[i * dim + i] = c(1.0, 0.0);
+        }
+        Self { dim, data }
+    }
+
+    /// A matrix from its row-major entries, which must form a `2^n x 2^n` matrix
+    pub(crate) fn from_entries(entries: &[Complex64]) -> Result<Self, Error> {
+        let dim = (entries.len() as f64).sqrt().round() as usize;
+        if dim * dim != entries.len() || !dim.is_power_of_two() {
+            return Err(Error::InvalidDimension(entries.len()));
+        }
+        Ok(Self {
+            dim,
+            data: entries.to_vec(),
+        })
+    }
+
+    fn diagonal(entries: &[Complex64]) -> Self {
+        let mut matrix = Self::identity(entries.len());
+        for (i, &entry) in entries.iter().enumerate() {
+            matrix.data[i * matrix.dim + i] = entry;
+        }
+        matrix
+    }
+
+    fn from_rows<const N: usize>(rows: [[Complex64; N]; N]) -> Self {
+        Self {
+            dim: N,
+            data: rows.into_iter().flatten().collect(),
+        }
+    }
+
+    /// The number of qubits the matrix acts on
+    pub(crate) fn qubits(&self) -> usize {
+        self.dim.trailing_zeros() as usize
+    }
+
+    fn get(&self, row: usize, column: usize) -> Complex64 {
+        self.data[row * self.dim + column]
+    }
+
+    pub(crate) fn adjoint(&self) -> Self {
+        let mut data = Vec::with_capacity(self.data.len());
+        for row in 0..self.dim {
+            for column in 0..self.dim {
+                data.push(self.get(column, row).conj());
+            }
+        }
+        Self {
+            dim: self.dim,
+            data,
+        }
+    }
+
+    pub(crate) fn mul(&self, other: &Matrix) -> Self {
+        let mut data = vec![Complex64::default(); self.data.len()];
+        for row in 0..self.dim {
+            for k in 0..self.dim {
+                let a = self.get(row, k);
+                if a == Complex64::default() {
+                    continue;
+                }
+                for column in 0..self.dim {
+                    data[row * self.dim + column] += a * other.get(k, column);
+                }
+            }
+        }
+        Self {
+            dim: self.dim,
+            data,
+        }
+    }
+
+    /// `[[I, 0], [0, self]]`: this matrix controlled on a new most significant qubit
+    fn controlled(&self) -> Self {
+        let dim = self.dim * 2;
+        let mut matrix = Self::identity(dim);
+        for row in 0..self.dim {
+            for column in 0..self.dim {
+                matrix.data[(self.dim + row) * dim + self.dim + column] = self.get(row, column);
+            }
+        }
+        matrix
+    }
+
+    /// The largest entry-wise difference between `U†U` and the identity. The entries must
+    /// be finite: a NaN deviation is dropped by the maximum.
+    pub(crate) fn unitarity_deviation(&self) -> f64 {
+        let product = self.adjoint().mul(self);
+        let identity = Self::identity(self.dim);
+        product
+            .data
+            .iter()
+            .zip(&identity.data)
+            .map(|(a, b)| (a - b).norm())
+            .fold(0.0, f64::max)
+    }
+
+    /// `1 - |tr(U†V)| / dim`: zero exactly when the matrices are equal up to a global phase
+    pub(crate) fn phase_invariant_distance(&self, other: &Matrix) -> f64 {
+        let trace = (0..self.dim)
+            .flat_map(|row| (0..self.dim).map(move |column| (row, column)))
+            .map(|(row, column)| self.get(row, column).conj() * other.get(row, column))
+            .sum::<Complex64>();
+        (1.0 - trace.norm() / self.dim as f64).max(0.0)
+    }
+
+    /// Left-multiply by `gate` acting on the qubits at `positions` of this matrix's
+    /// qubits, where position 0 is the most significant
+    fn apply(&mut self, gate: &Matrix, positions: &[usize]) {
+        let n = self.qubits();
+        let k = positions.len();
+        let shifts = positions.iter().map(|p| n - 1 - p).collect::<Vec<_>>();
+        let mask = shifts.iter().map(|s| 1 << s).sum::<usize>();
+        let offsets = (0..gate.dim)
+            .map(|g| {
+                (0..k)
+                    .filter(|j| (g >> (k - 1 - j)) & 1 == 1)
+                    .map(|j| 1 << shifts[j])
+                    .sum::<usize>()
+            })
+            .collect::<Vec<_>>();
+
+        let mut amplitudes = vec![Complex64::default(); gate.dim];
+        for column in 0..self.dim {
+            for base in (0..self.dim).filter(|base| base & mask == 0) {
+                for (amplitude, offset) in amplitudes.iter_mut().zip(&offsets) {
+                    *amplitude = self.data[(base + offset) * self.dim + column];
+                }
+                for (row, offset) in offsets.iter().enumerate() {
+                    self.data[(base + offset) * self.dim + column] = amplitudes
+                        .iter()
+                        .enumerate()
+                        .map(|(k, amplitude)| gate.get(row, k) * amplitude)
+                        .sum();
+                }
+            }
+        }
+    }
+}
+
+/// The matrix of one of Quil's standard gates
+fn standard_gate(name: &str, parameters: &[f64]) -> Option<Matrix> {
+    let (zero, one, i) = (c(0.0, 0.0), c(1.0, 0.0), c(0.0, 1.0));
+    let phase = |theta: f64| Complex64::from_polar(1.0, theta);
+    let x = Matrix::from_rows([[zero, one], [one, zero]]);
+    let swap = Matrix::from_rows([
+        [one, zero, zero, zero],
+        [zero, zero, one, zero],
+        [zero, one, zero, zero],
+        [zero, zero, zero, one],
+    ]);
+
+    let matrix = match (name, parameters) {
+        ("I", []) => Matrix::identity(2),
+        ("X", []) => x,
+        ("Y", []) => Matrix::from_rows([[zero, -i], [i, zero]]),
+        ("Z", []) => Matrix::diagonal(&[one, -one]),
+        ("H", []) => {
+            let h = c(std::f64::consts::FRAC_1_SQRT_2, 0.0);
+            Matrix::from_rows([[h, h], [h, -h]])
+        }
+        ("S", []) => Matrix::diagonal(&[one, i]),
+        ("T", []) => Matrix::diagonal(&[one, phase(std::f64::consts::FRAC_PI_4)]),
+        ("PHASE", &[theta]) => Matrix::diagonal(&[one, phase(theta)]),
+        ("RX", &[theta]) => {
+            let (s, co) = (theta / 2.0).sin_cos();
+            Matrix::from_rows([[c(co, 0.0), c(0.0, -s)], [c(0.0, -s), c(co, 0.0)]])
+        }
+        ("RY", &[theta]) => {
+            let (s, co) = (theta / 2.0).sin_cos();
+            Matrix::from_rows([[c(co, 0.0), c(-s, 0.0)], [c(s, 0.0), c(co, 0.0)]])
+        }
+        ("RZ", &[theta]) => Matrix::diagonal(&[phase(-theta / 2.0), phase(theta / 2.0)]),
+        ("CNOT", []) => x.controlled(),
+        ("CCNOT", []) => x.controlled().controlled(),
+        ("CZ", []) => Matrix::diagonal(&[one, one, one, -one]),
+        ("SWAP", []) => swap,
+        ("CSWAP", []) => swap.controlled(),
+        ("ISWAP", []) => Matrix::from_rows([
+            [one, zero, zero, zero],
+            [zero, zero, i, zero],
+            [zero, i, zero, zero],
+            [zero, zero, zero, one],
+        ]),
+        ("PSWAP", &[theta]) => Matrix::from_rows([
+            [one, zero, zero, zero],
+            [zero, zero, phase(theta), zero],
+            [zero, phase(theta), zero, zero],
+            [zero, zero, zero, one],
+        ]),
+        ("CPHASE", &[theta]) => Matrix::diagonal(&[one, one, one, phase(theta)]),
+        ("CPHASE00", &[theta]) => Matrix::diagonal(&[phase(theta), one, one, one]),
+        ("CPHASE01", &[theta]) => Matrix::diagonal(&[one, phase(theta), one, one]),
+        ("CPHASE10", &[theta]) => Matrix::diagonal(&[one, one, phase(theta), one]),
+        ("XY", &[theta]) => {
+            let (s, co) = (theta / 2.0).sin_cos();
+            Matrix::from_rows([
+                [one, zero, zero, zero],
+                [zero, c(co, 0.0), c(0.0, s), zero],
+                [zero, c(0.0, s), c(co, 0.0), zero],
+                [zero, zero, zero, one],
+            ])
+        }
+        _ => return None,
+    };
+    Some(matrix)
+}
+
+/// The matrix of a `DEFGATE` applied with the given parameters
+fn defined_gate(definition: &GateDefinition, parameters: &[f64]) -> Option<Matrix> {
+    if definition.parameters.len() != parameters.len() {
+        return None;
+    }
+    match &definition.specification {
+        GateSpecification::Matrix(rows) => {
+            let variables = definition
+                .parameters
+                .iter()
+                .cloned()
+                .zip(parameters.iter().map(|&p| Complex64::new(p, 0.0)))
+                .collect::<HashMap<_, _>>();
+            let entries = rows
+                .iter()
+                .flatten()
+                .map(|entry| entry.evaluate(&variables, &HashMap::new()).ok())
+                .collect::<Option<Vec<_>>>()?;
+            Matrix::from_entries(&entries).ok()
+        }
+        // Column `j` has its one in row `permutation[j]`
+        GateSpecification::Permutation(permutation) => {
+            let dim = permutation.len();
+            let mut data = vec![Complex64::default(); dim * dim];
+            for (column, &row) in permutation.iter().enumerate() {
+                *data.get_mut(usize::try_from(row).ok()? * dim + column)? = c(1.0, 0.0);
+            }
+            Matrix::from_entries(&data).ok()
+        }
+        GateSpecification::PauliSum(_) => None,
+    }
+}
+
+/// The matrix of a gate application, including its modifiers. Gates defined in the
+/// program take precedence over the standard gates.
+fn gate_matrix(
+    index: usize,
+    gate: &Gate,
+    definitions: &impl Fn(&str) -> Option<GateDefinition>,
+) -> Result<Matrix, Error> {
+    let parameters = real_parameters(&gate.parameters)
+        .into_iter()
+        .collect::<Option<Vec<_>>>()
+        .ok_or(Error::SymbolicParameter { index })?;
+    let unknown = || Error::UnknownGate {
+        index,
+        gate: quil_rs::quil::Quil::to_quil_or_debug(gate),
+    };
+
+    let mut matrix = match definitions(&gate.name) {
+        Some(definition) => defined_gate(&definition, &parameters),
+        None => standard_gate(&gate.name, &parameters),
+    }
+    .ok_or_else(unknown)?;
+    // The last modifier applies first
+    for modifier in gate.modifiers.iter().rev() {
+        matrix = match modifier {
+            GateModifier::Controlled => matrix.controlled(),
+            GateModifier::Dagger => matrix.adjoint(),
+            GateModifier::Forked => return Err(unknown()),
+        };
+    }
+    if matrix.qubits() != gate.qubits.len() {
+        return Err(unknown());
+    }
+    Ok(matrix)
+}
+
+/// The unitary of a gate-only program over `qubits`, the first being the most
+/// significant. Pragmas and fences are ignored.
+pub(crate) fn gates_unitary(program: &quil_rs::Program, qubits: &[u32]) -> Result<Matrix, Error> {
+    let definitions = |name: &str| program.gate_definitions.get(name).cloned();
+    let mut unitary = Matrix::identity(1 << qubits.len());
+    for (index, instruction) in program.body_instructions().enumerate() {
+        let gate = match instruction {
+            Instruction::Gate(gate) => gate,
+            Instruction::Pragma(_) | Instruction::Fence(_) => continue,
+            Instruction::Measurement(_) => return Err(Error::Measurement { index }),
+            Instruction::Label(_)
+            | Instruction::Jump(_)
+            | Instruction::JumpWhen(_)
+            | Instruction::JumpUnless(_)
+            | Instruction::Halt
+            | Instruction::Wait => return Err(Error::ControlFlow { index }),
+            _ => {
+                return Err(Error::NotAGate {
+                    index,
+                    instruction: quil_rs::quil::Quil::to_quil_or_debug(instruction),
+                })
+            }
+        };
+        let positions = gate
+            .qubits
+            .iter()
+            .map(|qubit| match qubit {
+                Qubit::Fixed(fixed) => qubits.iter().position(|q| u64::from(*q) == *fixed),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or(Error::UnexpectedQubit { index })?;
+        unitary.apply(&gate_matrix(index, gate, &definitions)?, &positions);
+    }
+    Ok(unitary)
+}
+
+/// The unitary of a program of gates on at most [`DEFAULT_MAX_QUBITS`] qubits; see
+/// [`program_unitary_with_limit`]
+#[cfg(feature = "ndarray")]
+pub fn program_unitary(program: &Program) -> Result<Array2<Complex64>, super::Error> {
+    program_unitary_with_limit(program, DEFAULT_MAX_QUBITS)
+}
+
+/// The unitary of a program made only of gates, over the fixed qubits it uses.
+///
+/// As in the QVM's wavefunction, the lowest-numbered qubit is the least significant
+/// bit of the matrix index. Standard gates, `DEFGATE`s given as matrices or permutations,
+/// and the `CONTROLLED` and `DAGGER` modifiers are supported. Measurements, control
+/// flow and classical instructions are errors, as is using more than `max_qubits`.
+#[cfg(feature = "ndarray")]
+pub fn program_unitary_with_limit(
+    program: &Program,
+    max_qubits: usize,
+) -> Result<Array2<Complex64>, super::Error> {
+    let program = program.to_quil_rs()?;
+    let mut qubits = fixed_qubits(&program);
+    if qubits.len() > max_qubits {
+        return Err(Error::TooManyQubits {
+            qubits: qubits.len(),
+            limit: max_qubits,
+        }
+        .into());
+    }
+    qubits.reverse();
+    let unitary = gates_unitary(&program, &qubits)?;
+    Ok(
+        Array2::from_shape_vec((unitary.dim, unitary.dim), unitary.data)
+            .expect("a unitary has dim * dim entries"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unitary(quil: &str, qubits: &[u32]) -> Matrix {
+        gates_unitary(&quil.parse().unwrap(), qubits).unwrap()
+    }
+
+    #[test]
+    fn test_gate_identities() {
+        let cnot = unitary("CNOT 0 1", &[0, 1]);
+        assert_eq!(cnot, standard_gate("CNOT", &[]).unwrap());
+        // The control is the most significant qubit, so listing qubits the other way
+        // round reverses the roles
+        let flipped = unitary("CNOT 1 0", &[0, 1]);
+        assert!(
+            unitary("H 0\nH 1\nCNOT 0 1\nH 0\nH 1", &[0, 1]).phase_invariant_distance(&flipped)
+                < 1e-12
+        );
+
+        let via_cz = unitary("H 1\nCZ 0 1\nH 1", &[0, 1]);
+        assert!(cnot.phase_invariant_distance(&via_cz) < 1e-12);
+        let via_rotations = unitary("RZ(pi/2) 0\nRX(pi/2) 0\nRZ(pi/2) 0", &[0]);
+        assert!(unitary("H 0", &[0]).phase_invariant_distance(&via_rotations) < 1e-12);
+        assert!(unitary("CONTROLLED X 0 1", &[0, 1]).phase_invariant_distance(&cnot) < 1e-12);
+        assert!(
+            unitary("DAGGER S 0\nS 0", &[0]).phase_invariant_distance(&Matrix::identity(2)) < 1e-12
+        );
+    }
+
+    #[test]
+    fn test_defined_gates() {
+        let defined = unitary(
+            "DEFGATE MYRX(%theta):\n    cos(%theta/2), -i*sin(%theta/2)\n    -i*sin(%theta/2), cos(%theta/2)\n\n\
+             DEFGATE MYCNOT AS PERMUTATION:\n    0, 1, 3, 2\n\n\
+             MYRX(0.3) 0\nMYCNOT 0 1",
+            &[0, 1],
+        );
+        let standard = unitary("RX(0.3) 0\nCNOT 0 1", &[0, 1]);
+        assert!(defined.phase_invariant_distance(&standard) < 1e-12);
+    }
+
+    #[test]
+    fn test_unitary_errors() {
+        assert_eq!(
+            gates_unitary(&"X 0\nMEASURE 0".parse().unwrap(), &[0]),
+            Err(Error::Measurement { index: 1 })
+        );
+        assert_eq!(
+            gates_unitary(&"X 0\nLABEL @a\nJUMP @a".parse().unwrap(), &[0]),
+            Err(Error::ControlFlow { index: 1 })
+        );
+        assert_eq!(
+            gates_unitary(&"RESET".parse().unwrap(), &[0]),
+            Err(Error::NotAGate {
+                index: 0,
+                instruction: "RESET".to_string()
+            })
+        );
+        assert_eq!(
+            gates_unitary(&"X 1".parse().unwrap(), &[0]),
+            Err(Error::UnexpectedQubit { index: 0 })
+        );
+        assert!(matches!(
+            gates_unitary(&"FOO 0".parse().unwrap(), &[0]),
+            Err(Error::UnknownGate { index: 0, .. })
+        ));
+
+        let not_unitary =
+            Matrix::from_entries(&[c(1.0, 0.0), c(1.0, 0.0), c(0.0, 0.0), c(1.0, 0.0)]).unwrap();
+        assert!(not_unitary.unitarity_deviation() > 0.5);
+        assert_eq!(
+            Matrix::from_entries(&[c(1.0, 0.0); 3]),
+            Err(Error::InvalidDimension(3))
+        );
+    }
+
+    #[cfg(feature = "ndarray")]
+    #[test]
+    fn test_program_unitary() {
+        let program: Program = "X 0".parse().unwrap();
+        let x = program_unitary(&program).unwrap();
+        assert_eq!(x.dim(), (2, 2));
+        assert_eq!(x[[1, 0]], c(1.0, 0.0));
+
+        // Qubit 0 is the least significant bit, so CNOT 0 1 maps |01> to |11>
+        let program: Program = "CNOT 0 1".parse().unwrap();
+        let cnot = program_unitary(&program).unwrap();
+        assert_eq!(cnot[[3, 1]], c(1.0, 0.0));
+
+        let program: Program = "H 0\nH 1\nH 2".parse().unwrap();
+        assert!(matches!(
+            program_unitary_with_limit(&program, 2),
+            Err(super::super::Error::Unitary(Error::TooManyQubits {
+                qubits: 3,
+                limit: 2
+            }))
+        ));
+    }
+}
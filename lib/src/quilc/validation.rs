@@ -0,0 +1,466 @@
+//! Validation of ISA JSON documents before they are handed to quilc, so that problems
+//! are reported against the offending entry rather than as a Lisp condition.
+
+use std::{collections::BTreeMap, fmt::Display};
+
+use serde_json::{Map, Value};
+
+/// A problem with an ISA document, located by a JSON pointer (RFC 6901)
+#[derive(Clone, Debug, PartialEq, thiserror::Error)]
+#[error("{pointer}: {reason}")]
+pub struct IsaError {
+    pub pointer: String,
+    pub reason: Reason,
+}
+
+#[derive(Clone, Debug, PartialEq, thiserror::Error)]
+pub enum Reason {
+    #[error("missing required key `{0}`")]
+    MissingKey(&'static str),
+    #[error("expected {expected}, found {found}")]
+    WrongType {
+        expected: &'static str,
+        found: &'static str,
+    },
+    #[error("references qubit {0}, which is not defined in `1Q`")]
+    DanglingQubit(u32),
+    #[error("duplicates the edge at {0}")]
+    DuplicateEdge(String),
+    #[error("{0}")]
+    InvalidValue(String),
+}
+
+/// Every problem found in an ISA document
+#[derive(Clone, Debug, PartialEq)]
+pub struct IsaErrors(pub Vec<IsaError>);
+
+impl Display for IsaErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let errors = self.0.iter().map(ToString::to_string).collect::<Vec<_>>();
+        write!(f, "{}", errors.join("; "))
+    }
+}
+
+impl std::error::Error for IsaErrors {}
+
+/// Validate an ISA JSON document of the form accepted by [`Chip::from_str`](super::Chip)
+pub fn validate_isa_json(json: &str) -> Result<(), IsaErrors> {
+    let value: Value = serde_json::from_str(json).map_err(|e| {
+        IsaErrors(vec![IsaError {
+            pointer: String::new(),
+            reason: Reason::InvalidValue(format!("invalid JSON: {e}")),
+        }])
+    })?;
+    validate_isa(&value)
+}
+
+/// Validate a parsed ISA document; see [`validate_isa_json`]
+pub fn validate_isa(value: &Value) -> Result<(), IsaErrors> {
+    let mut validator = Validator::default();
+    validator.document(value);
+    if validator.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(IsaErrors(validator.errors))
+    }
+}
+
+pub(crate) fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+/// Append a reference token to a JSON pointer, escaping it per RFC 6901
+pub(crate) fn join(pointer: &str, token: impl Display) -> String {
+    let token = token.to_string().replace('~', "~0").replace('/', "~1");
+    format!("{pointer}/{token}")
+}
+
+#[derive(Default)]
+struct Validator {
+    errors: Vec<IsaError>,
+}
+
+impl Validator {
+    fn error(&mut self, pointer: String, reason: Reason) {
+        self.errors.push(IsaError { pointer, reason });
+    }
+
+    fn wrong_type(&mut self, pointer: String, expected: &'static str, value: &Value) {
+        self.error(
+            pointer,
+            Reason::WrongType {
+                expected,
+                found: type_name(value),
+            },
+        );
+    }
+
+    fn object<'v>(&mut self, pointer: &str, value: &'v Value) -> Option<&'v Map<String, Value>> {
+        let object = value.as_object();
+        if object.is_none() {
+            self.wrong_type(pointer.to_string(), "an object", value);
+        }
+        object
+    }
+
+    fn required<'v>(
+        &mut self,
+        pointer: &str,
+        object: &'v Map<String, Value>,
+        key: &'static str,
+    ) -> Option<&'v Value> {
+        let value = object.get(key);
+        if value.is_none() {
+            self.error(pointer.to_string(), Reason::MissingKey(key));
+        }
+        value
+    }
+
+    fn qubit_id(&mut self, pointer: String, value: &Value) -> Option<u32> {
+        let id = value.as_u64().and_then(|id| u32::try_from(id).ok());
+        if id.is_none() {
+            self.wrong_type(pointer, "a qubit index", value);
+        }
+        id
+    }
+
+    fn optional_bool(&mut self, pointer: &str, object: &Map<String, Value>, key: &'static str) {
+        if let Some(value) = object.get(key).filter(|v| !v.is_boolean()) {
+            self.wrong_type(join(pointer, key), "a boolean", value);
+        }
+    }
+
+    fn document(&mut self, value: &Value) {
+        let Some(root) = self.object("", value) else {
+            return;
+        };
+        let Some(isa) = self.required("", root, "isa") else {
+            return;
+        };
+        let Some(isa) = self.object("/isa", isa) else {
+            return;
+        };
+
+        let qubits = self
+            .required("/isa", isa, "1Q")
+            .map(|qubits| self.qubits("/isa/1Q", qubits))
+            .unwrap_or_default();
+        if let Some(edges) = isa.get("2Q") {
+            self.edges("/isa/2Q", edges, &qubits);
+        }
+    }
+
+    /// Validate the `1Q` section, returning the ids of the qubits it defines
+    fn qubits(&mut self, pointer: &str, value: &Value) -> Vec<u32> {
+        let Some(qubits) = self.object(pointer, value) else {
+            return vec![];
+        };
+
+        let mut ids = vec![];
+        for (key, qubit) in qubits {
+            let pointer = join(pointer, key);
+            let Ok(id) = key.parse::<u32>() else {
+                self.error(
+                    pointer,
+                    Reason::InvalidValue(format!("qubit key `{key}` is not a qubit index")),
+                );
+                continue;
+            };
+            ids.push(id);
+
+            let Some(qubit) = self.object(&pointer, qubit) else {
+                continue;
+            };
+            if let Some(value) = qubit.get("id") {
+                if let Some(actual) = self.qubit_id(join(&pointer, "id"), value) {
+                    if actual != id {
+                        self.error(
+                            join(&pointer, "id"),
+                            Reason::InvalidValue(format!("id {actual} does not match key `{key}`")),
+                        );
+                    }
+                }
+            }
+            self.optional_bool(&pointer, qubit, "dead");
+            if let Some(gates) = qubit.get("gates") {
+                self.operations(&join(&pointer, "gates"), gates, &[id]);
+            }
+        }
+        ids
+    }
+
+    fn edges(&mut self, pointer: &str, value: &Value, qubits: &[u32]) {
+        let Some(edges) = self.object(pointer, value) else {
+            return;
+        };
+
+        let mut seen: BTreeMap<(u32, u32), String> = BTreeMap::new();
+        for (key, edge) in edges {
+            let pointer = join(pointer, key);
+            let ids = key
+                .split_once('-')
+                .and_then(|(a, b)| Some((a.parse::<u32>().ok()?, b.parse::<u32>().ok()?)));
+            let Some((a, b)) = ids else {
+                self.error(
+                    pointer,
+                    Reason::InvalidValue(format!("edge key `{key}` is not of the form `<q>-<q>`")),
+                );
+                continue;
+            };
+            if a == b {
+                self.error(
+                    pointer.clone(),
+                    Reason::InvalidValue(format!("edge `{key}` connects qubit {a} to itself")),
+                );
+            }
+            for id in [a, b] {
+                if !qubits.contains(&id) {
+                    self.error(pointer.clone(), Reason::DanglingQubit(id));
+                }
+            }
+            if let Some(previous) = seen.insert((a.min(b), a.max(b)), pointer.clone()) {
+                self.error(pointer.clone(), Reason::DuplicateEdge(previous));
+            }
+
+            let Some(edge) = self.object(&pointer, edge) else {
+                continue;
+            };
+            if let Some(value) = edge.get("ids") {
+                self.edge_ids(&join(&pointer, "ids"), value, (a, b));
+            }
+            self.optional_bool(&pointer, edge, "dead");
+            if let Some(gates) = edge.get("gates") {
+                self.operations(&join(&pointer, "gates"), gates, &[a, b]);
+            }
+        }
+    }
+
+    fn edge_ids(&mut self, pointer: &str, value: &Value, (a, b): (u32, u32)) {
+        let Some(ids) = value.as_array() else {
+            self.wrong_type(pointer.to_string(), "an array", value);
+            return;
+        };
+        if ids.len() != 2 {
+            self.error(
+                pointer.to_string(),
+                Reason::InvalidValue(format!("expected 2 qubit ids, found {}", ids.len())),
+            );
+            return;
+        }
+        let ids = ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| self.qubit_id(join(pointer, i), id))
+            .collect::<Option<Vec<_>>>();
+        if let Some(ids) = ids {
+            if (ids[0].min(ids[1]), ids[0].max(ids[1])) != (a.min(b), a.max(b)) {
+                self.error(
+                    pointer.to_string(),
+                    Reason::InvalidValue(format!(
+                        "ids [{}, {}] do not match key `{a}-{b}`",
+                        ids[0], ids[1]
+                    )),
+                );
+            }
+        }
+    }
+
+    fn operations(&mut self, pointer: &str, value: &Value, qubits: &[u32]) {
+        let Some(operations) = value.as_array() else {
+            self.wrong_type(pointer.to_string(), "an array", value);
+            return;
+        };
+
+        for (i, operation) in operations.iter().enumerate() {
+            let pointer = join(pointer, i);
+            let Some(operation) = self.object(&pointer, operation) else {
+                continue;
+            };
+            if let Some(operator) = self.required(&pointer, operation, "operator") {
+                if !operator.is_string() {
+                    self.wrong_type(join(&pointer, "operator"), "a string", operator);
+                }
+            }
+            let operator_type = operation.get("operator_type");
+            match operator_type.map(|t| (t, t.as_str())) {
+                None | Some((_, Some("gate"))) => self.gate(&pointer, operation, qubits),
+                Some((_, Some("measure"))) => self.measure(&pointer, operation, qubits),
+                Some((value, _)) => self.error(
+                    join(&pointer, "operator_type"),
+                    Reason::InvalidValue(format!("expected `gate` or `measure`, found {value}")),
+                ),
+            }
+            for key in ["duration", "fidelity"] {
+                if let Some(value) = operation.get(key) {
+                    match value.as_f64() {
+                        None => self.wrong_type(join(&pointer, key), "a number", value),
+                        Some(v) if v < 0.0 => self.error(
+                            join(&pointer, key),
+                            Reason::InvalidValue(format!("{key} must not be negative")),
+                        ),
+                        Some(v) if key == "fidelity" && v > 1.0 => self.error(
+                            join(&pointer, key),
+                            Reason::InvalidValue("fidelity must not exceed 1".to_string()),
+                        ),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    fn gate(&mut self, pointer: &str, gate: &Map<String, Value>, qubits: &[u32]) {
+        if let Some(value) = gate.get("parameters") {
+            match value.as_array() {
+                None => self.wrong_type(join(pointer, "parameters"), "an array", value),
+                Some(parameters) => {
+                    for (i, parameter) in parameters.iter().enumerate() {
+                        if !parameter.is_number() && !parameter.is_string() {
+                            self.wrong_type(
+                                join(&join(pointer, "parameters"), i),
+                                "a number or a string",
+                                parameter,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(value) = gate.get("arguments") {
+            match value.as_array() {
+                None => self.wrong_type(join(pointer, "arguments"), "an array", value),
+                Some(arguments) => {
+                    for (i, argument) in arguments.iter().enumerate() {
+                        self.argument(join(&join(pointer, "arguments"), i), argument, qubits);
+                    }
+                }
+            }
+        }
+    }
+
+    fn measure(&mut self, pointer: &str, measure: &Map<String, Value>, qubits: &[u32]) {
+        if let Some(qubit) = self.required(pointer, measure, "qubit") {
+            self.argument(join(pointer, "qubit"), qubit, qubits);
+        }
+        if let Some(target) = measure
+            .get("target")
+            .filter(|t| !t.is_string() && !t.is_null())
+        {
+            self.wrong_type(join(pointer, "target"), "a string or null", target);
+        }
+    }
+
+    /// A gate argument must be `"_"` or one of the qubits of the enclosing entry
+    fn argument(&mut self, pointer: String, value: &Value, qubits: &[u32]) {
+        match value {
+            Value::String(_) => {}
+            Value::Number(_) => {
+                if let Some(id) = self.qubit_id(pointer.clone(), value) {
+                    if !qubits.contains(&id) {
+                        self.error(pointer, Reason::DanglingQubit(id));
+                    }
+                }
+            }
+            _ => self.wrong_type(pointer, "a qubit index or a string", value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quilc::fixtures::read_data_file;
+    use serde_json::json;
+
+    fn errors(value: Value) -> Vec<(String, Reason)> {
+        validate_isa(&value)
+            .err()
+            .map(|errors| {
+                errors
+                    .0
+                    .into_iter()
+                    .map(|e| (e.pointer, e.reason))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_aspen_9_is_valid() {
+        validate_isa_json(&read_data_file("aspen-9-isa.json")).unwrap();
+    }
+
+    #[test]
+    fn test_missing_keys_and_bad_types() {
+        assert_eq!(
+            errors(json!({"specs": {}})),
+            vec![(String::new(), Reason::MissingKey("isa"))]
+        );
+        assert_eq!(
+            errors(json!({"isa": {
+                "1Q": {"3": {"id": 3, "dead": "no", "gates": [{"operator_type": "gate"}]}},
+                "2Q": {},
+            }})),
+            vec![
+                (
+                    "/isa/1Q/3/dead".to_string(),
+                    Reason::WrongType {
+                        expected: "a boolean",
+                        found: "a string"
+                    }
+                ),
+                (
+                    "/isa/1Q/3/gates/0".to_string(),
+                    Reason::MissingKey("operator")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bad_fidelity() {
+        let gate = json!({"operator": "CZ", "fidelity": "high", "arguments": ["_", "_"]});
+        assert_eq!(
+            errors(json!({"isa": {
+                "1Q": {"3": {"id": 3}, "4": {"id": 4}},
+                "2Q": {"3-4": {"ids": [3, 4], "gates": [gate]}},
+            }})),
+            vec![(
+                "/isa/2Q/3-4/gates/0/fidelity".to_string(),
+                Reason::WrongType {
+                    expected: "a number",
+                    found: "a string"
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn test_dangling_and_duplicate_edges() {
+        assert_eq!(
+            errors(json!({"isa": {
+                "1Q": {"0": {"id": 0}, "1": {"id": 1}},
+                "2Q": {"0-1": {"ids": [0, 1]}, "1-0": {"ids": [1, 0]}, "1-5": {"ids": [1, 5]}},
+            }})),
+            vec![
+                (
+                    "/isa/2Q/1-0".to_string(),
+                    Reason::DuplicateEdge("/isa/2Q/0-1".to_string())
+                ),
+                ("/isa/2Q/1-5".to_string(), Reason::DanglingQubit(5)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_json_pointer_escaping() {
+        assert_eq!(join("/isa", "a/b~c"), "/isa/a~1b~0c");
+    }
+}
@@ -34,6 +34,7 @@ pub enum Error {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VersionInfo {
     pub version: String,
     pub githash: String,
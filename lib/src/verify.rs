@@ -0,0 +1,380 @@
+//! Checking that a compiled program does what the original program did
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use num_complex::Complex64;
+use quil_rs::instruction::{Instruction, MemoryReference, Qubit};
+
+use crate::{
+    quilc::{
+        self, check_protoquil,
+        metadata::rewirings,
+        placement::{fixed_qubits, relabel_qubits},
+        unitary::{self, gates_unitary, Matrix, DEFAULT_MAX_QUBITS},
+        CompilationResult, Program,
+    },
+    qvm,
+};
+
+/// The qubit measured into each memory location, keyed by region name and index
+type Measured = BTreeMap<(String, u64), u32>;
+
+/// The tolerance used by [`verify_compilation`]
+pub const DEFAULT_TOLERANCE: f64 = 1e-6;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Quilc(#[from] quilc::Error),
+    #[error(transparent)]
+    Qvm(#[from] qvm::Error),
+    #[error("cannot verify the compilation: {0}")]
+    Unverifiable(String),
+}
+
+impl From<unitary::Error> for Error {
+    fn from(error: unitary::Error) -> Self {
+        quilc::Error::from(error).into()
+    }
+}
+
+/// How two programs were compared
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    /// Their unitaries, up to a global phase
+    Unitary,
+    /// The QVM's distributions over the memory they measure into
+    Distribution,
+}
+
+/// The outcome of [`verify_compilation`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VerificationReport {
+    pub method: Method,
+    /// The largest entry-wise difference between the unitaries, after removing the global
+    /// phase, or between the probabilities of any measurement outcome
+    pub max_deviation: f64,
+    pub tolerance: f64,
+    /// Whether `max_deviation` is within `tolerance`
+    pub passed: bool,
+}
+
+impl VerificationReport {
+    fn new(method: Method, max_deviation: f64, tolerance: f64) -> Self {
+        Self {
+            method,
+            max_deviation,
+            tolerance,
+            passed: max_deviation <= tolerance,
+        }
+    }
+}
+
+/// Verify a compilation with [`DEFAULT_TOLERANCE`] on programs of up to
+/// [`DEFAULT_MAX_QUBITS`] qubits; see [`verify_compilation_with`]
+pub fn verify_compilation(
+    original: &Program,
+    result: &CompilationResult,
+) -> Result<VerificationReport, Error> {
+    verify_compilation_with(original, result, DEFAULT_TOLERANCE, DEFAULT_MAX_QUBITS)
+}
+
+/// Compare a program with the result of compiling it.
+///
+/// A program without measurements is compared by unitary. The compiled program's qubits
+/// are mapped back through the initial placement quilc records in its first rewiring
+/// pragma, and its final permutation through [`CompilationMetadata::final_rewiring`]
+/// (or its last rewiring pragma). `original` must be the program quilc saw, so for an
+/// explicit [`Placement`](quilc::Placement) pass the output of `Placement::apply`.
+///
+/// A program with measurements is compared by the distribution over the memory it
+/// measures into, computed by the QVM with measurements removed. This requires every
+/// measurement to be final, as in protoquil.
+///
+/// [`CompilationMetadata::final_rewiring`]: quilc::CompilationMetadata::final_rewiring
+pub fn verify_compilation_with(
+    original: &Program,
+    result: &CompilationResult,
+    tolerance: f64,
+    max_qubits: usize,
+) -> Result<VerificationReport, Error> {
+    let compiled = result.program.to_quil_rs()?;
+    let measures = original
+        .to_quil_rs()?
+        .body_instructions()
+        .any(|instruction| matches!(instruction, Instruction::Measurement(_)));
+    if measures {
+        compare_distributions(original, &result.program, tolerance, max_qubits)
+    } else {
+        let mut rewirings = rewirings(&compiled);
+        if let Some(metadata) = result.metadata.as_ref() {
+            if !metadata.final_rewiring.is_empty() {
+                rewirings.push(metadata.final_rewiring.clone());
+            }
+        }
+        let initial = rewirings.first().cloned().unwrap_or_default();
+        let last = rewirings.last().cloned().unwrap_or_default();
+        compare_unitaries(
+            &original.to_quil_rs()?,
+            &compiled,
+            &initial,
+            &last,
+            tolerance,
+            max_qubits,
+        )
+    }
+}
+
+/// Look a logical qubit up in a rewiring, which leaves qubits it doesn't cover in place
+fn lookup(rewiring: &[u32], logical: u32) -> u32 {
+    rewiring.get(logical as usize).copied().unwrap_or(logical)
+}
+
+fn compare_unitaries(
+    original: &quil_rs::Program,
+    compiled: &quil_rs::Program,
+    initial: &[u32],
+    last: &[u32],
+    tolerance: f64,
+    max_qubits: usize,
+) -> Result<VerificationReport, Error> {
+    let logical = fixed_qubits(original);
+    let physical = fixed_qubits(compiled)
+        .into_iter()
+        .chain(logical.iter().map(|&l| lookup(initial, l)))
+        .chain(logical.iter().map(|&l| lookup(last, l)))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    if physical.len() > max_qubits {
+        return Err(unitary::Error::TooManyQubits {
+            qubits: physical.len(),
+            limit: max_qubits,
+        }
+        .into());
+    }
+
+    // Where the qubit starting on each physical qubit ends up
+    let occupant = |p: u32| initial.iter().position(|&q| q == p).map_or(p, |l| l as u32);
+    let destinations = physical
+        .iter()
+        .map(|&p| {
+            let destination = lookup(last, occupant(p));
+            physical
+                .iter()
+                .position(|&q| q == destination)
+                .ok_or_else(|| {
+                    Error::Unverifiable(format!(
+                        "the rewiring moves the qubit on {p} to {destination}, outside the program"
+                    ))
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let placed = relabel_qubits(original, |l| Some(lookup(initial, l)));
+    let expected = permute(&gates_unitary(&placed, &physical)?, &destinations);
+    let actual = gates_unitary(compiled, &physical)?;
+    Ok(VerificationReport::new(
+        Method::Unitary,
+        max_deviation_up_to_phase(&expected, &actual),
+        tolerance,
+    ))
+}
+
+/// Follow `unitary` with moving the qubit at each position `i` to `destinations[i]`,
+/// where position 0 is the most significant
+fn permute(unitary: &Matrix, destinations: &[usize]) -> Matrix {
+    let n = destinations.len();
+    let mut permuted = unitary.clone();
+    for row in 0..unitary.dim {
+        let target = (0..n)
+            .filter(|&i| (row >> (n - 1 - i)) & 1 == 1)
+            .map(|i| 1 << (n - 1 - destinations[i]))
+            .sum::<usize>();
+        permuted.data[target * unitary.dim..(target + 1) * unitary.dim]
+            .copy_from_slice(&unitary.data[row * unitary.dim..(row + 1) * unitary.dim]);
+    }
+    permuted
+}
+
+/// The largest entry-wise difference between `actual` and `expected` after rotating
+/// `expected` by the global phase that best aligns them
+fn max_deviation_up_to_phase(expected: &Matrix, actual: &Matrix) -> f64 {
+    let overlap = expected
+        .data
+        .iter()
+        .zip(&actual.data)
+        .map(|(e, a)| e.conj() * a)
+        .sum::<Complex64>();
+    let phase = if overlap.norm() > f64::EPSILON {
+        overlap / overlap.norm()
+    } else {
+        Complex64::new(1.0, 0.0)
+    };
+    expected
+        .data
+        .iter()
+        .zip(&actual.data)
+        .map(|(e, a)| (a - phase * e).norm())
+        .fold(0.0, f64::max)
+}
+
+fn compare_distributions(
+    original: &Program,
+    compiled: &Program,
+    tolerance: f64,
+    max_qubits: usize,
+) -> Result<VerificationReport, Error> {
+    let (original, original_memory) = measurement_free(original)?;
+    let (compiled, compiled_memory) = measurement_free(compiled)?;
+    if original_memory.keys().ne(compiled_memory.keys()) {
+        return Err(Error::Unverifiable(
+            "the programs measure into different memory".to_string(),
+        ));
+    }
+
+    let expected = distribution(&original, &original_memory, max_qubits)?;
+    let actual = distribution(&compiled, &compiled_memory, max_qubits)?;
+    let max_deviation = expected
+        .iter()
+        .zip(&actual)
+        .map(|(e, a)| (e - a).abs())
+        .fold(0.0, f64::max);
+    Ok(VerificationReport::new(
+        Method::Distribution,
+        max_deviation,
+        tolerance,
+    ))
+}
+
+/// The program with its measurements removed, and the qubit measured into each memory
+/// location
+fn measurement_free(program: &Program) -> Result<(quil_rs::Program, Measured), Error> {
+    if let Some(violation) = check_protoquil(program)?.first() {
+        return Err(Error::Unverifiable(violation.to_string()));
+    }
+
+    let program = program.to_quil_rs()?;
+    let mut memory = BTreeMap::new();
+    let mut instructions = vec![];
+    for instruction in program.body_instructions() {
+        match instruction {
+            Instruction::Measurement(measurement) => {
+                let Qubit::Fixed(qubit) = measurement.qubit else {
+                    return Err(Error::Unverifiable(
+                        "a measurement is not of a fixed qubit".to_string(),
+                    ));
+                };
+                if let Some(MemoryReference { name, index }) = &measurement.target {
+                    memory.insert((name.clone(), *index), qubit as u32);
+                }
+            }
+            instruction => instructions.push(instruction.clone()),
+        }
+    }
+    let mut stripped = program.clone_without_body_instructions();
+    stripped.add_instructions(instructions);
+    Ok((stripped, memory))
+}
+
+/// The QVM's distribution over the values of `memory`, the first location being the
+/// least significant bit of the outcome index
+fn distribution(
+    program: &quil_rs::Program,
+    memory: &Measured,
+    max_qubits: usize,
+) -> Result<Vec<f64>, Error> {
+    // Run on qubits 0..n so that the QVM's state covers only the qubits in use
+    let qubits = fixed_qubits(program)
+        .into_iter()
+        .chain(memory.values().copied())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    if qubits.len() > max_qubits {
+        return Err(unitary::Error::TooManyQubits {
+            qubits: qubits.len(),
+            limit: max_qubits,
+        }
+        .into());
+    }
+    let dense = |qubit: u32| qubits.iter().position(|&q| q == qubit).map(|i| i as u32);
+    let program = Program::from_quil_rs(&relabel_qubits(program, dense))?;
+    let probabilities = qvm::probabilities(&program, qubits.len() as u32, None)?;
+
+    let measured = memory
+        .values()
+        .map(|&qubit| dense(qubit).expect("measured qubits are in use"))
+        .collect::<Vec<_>>();
+    let mut distribution = vec![0.0; 1 << measured.len()];
+    for (state, probability) in probabilities.into_iter().enumerate() {
+        let outcome = measured
+            .iter()
+            .enumerate()
+            .filter(|(_, &qubit)| (state >> qubit) & 1 == 1)
+            .map(|(bit, _)| 1 << bit)
+            .sum::<usize>();
+        distribution[outcome] += probability;
+    }
+    Ok(distribution)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quilc::compile_program;
+    use crate::quilc::fixtures::aspen_9;
+
+    fn unitary_report(
+        original: &str,
+        compiled: &str,
+        initial: &[u32],
+        last: &[u32],
+    ) -> VerificationReport {
+        compare_unitaries(
+            &original.parse().unwrap(),
+            &compiled.parse().unwrap(),
+            initial,
+            last,
+            DEFAULT_TOLERANCE,
+            DEFAULT_MAX_QUBITS,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_compare_unitaries() {
+        // Equal up to a global phase
+        let report = unitary_report("H 0", "RZ(pi/2) 0\nRX(pi/2) 0\nRZ(pi/2) 0", &[], &[]);
+        assert!(report.passed, "{report:?}");
+        assert_eq!(report.method, Method::Unitary);
+
+        let report = unitary_report("H 0", "X 0", &[], &[]);
+        assert!(!report.passed);
+
+        // Logical qubits 0 and 1 start on physical qubits 5 and 6, and a SWAP exchanges them
+        let report = unitary_report("X 0\nCNOT 0 1", "X 5\nCNOT 5 6\nSWAP 5 6", &[5, 6], &[6, 5]);
+        assert!(report.passed, "{report:?}");
+        let report = unitary_report("X 0\nCNOT 0 1", "X 5\nCNOT 5 6\nSWAP 5 6", &[5, 6], &[5, 6]);
+        assert!(!report.passed);
+    }
+
+    #[test]
+    fn test_verify_compilation() {
+        let chip = aspen_9();
+
+        let program: Program = "H 0\nCNOT 0 1\nCNOT 1 2".parse().unwrap();
+        let result = compile_program(&program, &chip).unwrap();
+        let report = verify_compilation(&program, &result).unwrap();
+        assert_eq!(report.method, Method::Unitary);
+        assert!(report.passed, "{report:?}");
+
+        let program: Program = "DECLARE ro BIT[2]\nH 0\nCNOT 0 1\nMEASURE 0 ro[0]\nMEASURE 1 ro[1]"
+            .parse()
+            .unwrap();
+        let result = compile_program(&program, &chip).unwrap();
+        let report = verify_compilation(&program, &result).unwrap();
+        assert_eq!(report.method, Method::Distribution);
+        assert!(report.passed, "{report:?}");
+    }
+}
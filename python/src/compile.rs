@@ -1,23 +1,73 @@
-use pyo3::{exceptions::PyValueError, prelude::*, types::PyBool};
+use libquil_sys::quilc::{self, RewiringStrategy};
+use pyo3::{
+    exceptions::PyValueError,
+    prelude::*,
+    types::{PyBool, PyLong, PyString},
+};
 use rigetti_pyo3::{py_wrap_data_struct, PyWrapper, ToPythonError};
 
 #[derive(Clone)]
 pub struct CompileOptions {
     pub protoquil: Option<bool>,
+    pub initial_rewiring: Option<String>,
+    pub return_metadata: Option<bool>,
+    pub seed: Option<u64>,
 }
 
 py_wrap_data_struct! {
     PyCompileOptions(CompileOptions) as "CompileOptions" {
-        protoquil: Option<bool> => Option<Py<PyBool>>
+        protoquil: Option<bool> => Option<Py<PyBool>>,
+        initial_rewiring: Option<String> => Option<Py<PyString>>,
+        return_metadata: Option<bool> => Option<Py<PyBool>>,
+        seed: Option<u64> => Option<Py<PyLong>>
     }
 }
 
 #[pymethods]
 impl PyCompileOptions {
     #[new]
-    pub fn new(py: Python<'_>, protoquil: Option<Py<PyBool>>) -> PyResult<Self> {
-        let protoquil = protoquil.map(|p| p.is_true(py)).transpose()?;
-        Ok(Self(CompileOptions { protoquil }))
+    #[pyo3(signature = (protoquil=None, initial_rewiring=None, return_metadata=None, seed=None))]
+    pub fn new(
+        protoquil: Option<bool>,
+        initial_rewiring: Option<String>,
+        return_metadata: Option<bool>,
+        seed: Option<u64>,
+    ) -> PyResult<Self> {
+        let options = CompileOptions {
+            protoquil,
+            initial_rewiring,
+            return_metadata,
+            seed,
+        };
+        // Reject an unknown rewiring strategy up front rather than at compile time
+        options.to_quilc()?;
+        Ok(Self(options))
+    }
+}
+
+impl CompileOptions {
+    fn to_quilc(&self) -> PyResult<quilc::CompileOptions> {
+        let defaults = quilc::CompileOptions::default();
+        let initial_rewiring = self
+            .initial_rewiring
+            .as_deref()
+            .map(|strategy| match strategy.to_uppercase().as_str() {
+                "NAIVE" => Ok(RewiringStrategy::Naive),
+                "PARTIAL" => Ok(RewiringStrategy::Partial),
+                "GREEDY" => Ok(RewiringStrategy::Greedy),
+                "RANDOM" => Ok(RewiringStrategy::Random),
+                _ => Err(PyValueError::new_err(format!(
+                    "unknown initial rewiring strategy: {strategy}"
+                ))),
+            })
+            .transpose()?;
+
+        Ok(quilc::CompileOptions {
+            protoquil: self.protoquil.unwrap_or(defaults.protoquil),
+            initial_rewiring: initial_rewiring.map(Into::into),
+            return_metadata: self.return_metadata.unwrap_or(defaults.return_metadata),
+            seed: self.seed,
+        })
     }
 }
 
@@ -94,15 +144,15 @@ pub fn compile(
     chip: &crate::chip::PyChip,
     options: Option<&PyCompileOptions>,
 ) -> PyResult<PyCompilationResult> {
-    let protoquil = options.and_then(|e| e.as_inner().protoquil);
-
-    let compilation_result = if let Some(true) = protoquil {
-        libquil_sys::quilc::compile_protoquil(&program.as_inner().0, &chip.as_inner().0)
-            .map_err(|e| crate::RustLibquilQuilcError::from(e).to_py_err())?
-    } else {
-        libquil_sys::quilc::compile_program(&program.as_inner().0, &chip.as_inner().0)
-            .map_err(|e| crate::RustLibquilQuilcError::from(e).to_py_err())?
-    };
+    let options = options
+        .map(|options| options.as_inner().to_quilc())
+        .transpose()?
+        .unwrap_or_default();
+
+    let compilation_result = quilc::Compiler::new(&chip.as_inner().0)
+        .with_options(options)
+        .compile(&program.as_inner().0)
+        .map_err(|e| crate::RustLibquilQuilcError::from(e).to_py_err())?;
 
     let metadata = compilation_result.metadata.map(PyCompilationMetadata);
 